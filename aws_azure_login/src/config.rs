@@ -1,9 +1,12 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use dialoguer::theme::ColorfulTheme;
 use dialoguer::Input;
 use file_manager::aws_config::AwsConfig;
+use sso::cloud::AzureCloud;
+use sso::federation_metadata::{store_certificates, FederationMetadata};
 use std::collections::HashMap;
-use tracing::log::info;
+use std::path::Path;
+use tracing::log::{info, warn};
 
 pub fn configure_profile(
     profiles: &mut HashMap<String, AwsConfig>,
@@ -140,6 +143,7 @@ pub fn configure_profile(
             Some(okta_default_password)
         },
         credential_process: None,
+        ..profile
     };
 
     AwsConfig::upsert(profile_name, &new_profile, profiles)?;
@@ -147,3 +151,83 @@ pub fn configure_profile(
 
     Ok(())
 }
+
+pub fn import_metadata(
+    profiles: &mut HashMap<String, AwsConfig>,
+    profile_name: &str,
+    metadata_path: &Path,
+    app_id_uri: Option<&str>,
+    region: Option<&str>,
+    verify: bool,
+) -> Result<()> {
+    let metadata = FederationMetadata::load(metadata_path)?;
+    let mut profile = AwsConfig::get(profile_name, profiles).unwrap_or_default();
+
+    info!(
+        "Importing federation metadata into profile: {}",
+        profile_name
+    );
+
+    let tenant_id = metadata.tenant_id.clone().ok_or(anyhow!(
+        "Unable to find the tenant ID in the federation metadata"
+    ))?;
+
+    if let Some(region) = region {
+        profile.region = Some(region.to_string());
+    }
+    if let Some(app_id_uri) = app_id_uri {
+        profile.azure_app_id_uri = Some(app_id_uri.to_string());
+    }
+    if profile.azure_app_id_uri == AwsConfig::default().azure_app_id_uri {
+        warn!(
+            "azure_app_id_uri is the default {}, pass --app-id-uri if the enterprise application uses another identifier",
+            profile.azure_app_id_uri.as_deref().unwrap_or_default()
+        );
+    }
+
+    // Only pin the authority host when the metadata points somewhere the profile doesn't already log in
+    if let Some(host) = metadata.authority_host() {
+        let current_host = match &profile.azure_authority_host {
+            Some(host) => host.clone(),
+            None => AzureCloud::from_config(&profile)?
+                .authority_host()
+                .to_string(),
+        };
+        if normalize_host(&host) != normalize_host(&current_host) {
+            profile.azure_authority_host = Some(host);
+        }
+    }
+
+    let certificate_path = store_certificates(profile_name, &metadata.signing_certificates)?;
+    for certificate in &metadata.signing_certificates {
+        info!(
+            "Signing certificate {} valid until {}",
+            certificate.thumbprint(),
+            certificate.not_after()?
+        );
+    }
+
+    profile.azure_tenant_id = Some(tenant_id);
+    profile.azure_idp_entity_id = Some(metadata.entity_id);
+    profile.azure_signing_certificate = Some(certificate_path.display().to_string());
+    if verify {
+        profile.azure_verify_saml = Some(true);
+    }
+
+    AwsConfig::upsert(profile_name, &profile, profiles)?;
+    AwsConfig::write(profiles)?;
+
+    info!(
+        "Stored {} signing certificate(s) in {}",
+        metadata.signing_certificates.len(),
+        certificate_path.display()
+    );
+
+    Ok(())
+}
+
+fn normalize_host(host: &str) -> &str {
+    host.trim()
+        .trim_start_matches("https://")
+        .trim_end_matches('/')
+}
@@ -0,0 +1,140 @@
+use anyhow::{bail, Result};
+use file_manager::account_alias::AccountAlias;
+use file_manager::aws_config::AwsConfig;
+use sso::saml_response::Role;
+use std::collections::{BTreeSet, HashMap};
+use tracing::log::info;
+
+pub fn discover_profiles(
+    profiles: &mut HashMap<String, AwsConfig>,
+    source_profile: &str,
+    roles: &[Role],
+    template: &str,
+    dry_run: bool,
+    prune: bool,
+) -> Result<()> {
+    let source = AwsConfig::get(source_profile, profiles)?;
+    let source_name = source_profile.trim_start_matches("profile ").to_string();
+    let source_key = AwsConfig::sanitize_profile_name(source_profile);
+
+    if let Some(discovered_from) = &source.azure_discovered_from {
+        bail!(
+            "Profile '{}' was generated by discover, run it with --profile {} instead",
+            source_name,
+            discovered_from
+        );
+    }
+
+    let aliases = AccountAlias::read_aliases()?;
+    let mut generated = BTreeSet::new();
+
+    for role in roles {
+        let account_alias = aliases
+            .get(role.account_id())
+            .map(|alias| alias.as_str())
+            .unwrap_or(role.account_id());
+        let profile_name = render_profile_name(template, role, account_alias, &source_name);
+        let key = AwsConfig::sanitize_profile_name(&profile_name);
+
+        if key == source_key {
+            info!(
+                "Skipping {}, it is the source profile ({})",
+                profile_name, role.role_arn
+            );
+            continue;
+        }
+
+        if !generated.insert(key.clone()) {
+            info!(
+                "Skipping {}, the name is already used for another role ({})",
+                profile_name, role.role_arn
+            );
+            continue;
+        }
+
+        let existing = profiles.get(&key);
+        if existing.is_some_and(|existing| {
+            existing.azure_discovered_from.as_deref() != Some(source_name.as_str())
+        }) {
+            info!(
+                "Skipping {}, it was not generated from {}",
+                profile_name, source_name
+            );
+            continue;
+        }
+
+        let profile = AwsConfig {
+            azure_default_role_arn: Some(role.role_arn.clone()),
+            azure_discovered_from: Some(source_name.clone()),
+            credential_process: None,
+            // The generated profile assumes the discovered role directly, without the source's role selection or chain
+            azure_fallback_roles: None,
+            azure_role_filter: None,
+            role_arn: None,
+            source_profile: None,
+            external_id: None,
+            role_session_name: None,
+            azure_chain_role_arn: None,
+            azure_chain_external_id: None,
+            azure_chain_session_name: None,
+            azure_chain_cache: None,
+            ..source.clone()
+        };
+
+        match existing {
+            Some(existing) if *existing == profile => {
+                info!("Unchanged {} ({})", profile_name, role.role_arn);
+                continue;
+            }
+            Some(_) => info!("Updating {} ({})", profile_name, role.role_arn),
+            None => info!("Creating {} ({})", profile_name, role.role_arn),
+        }
+
+        if !dry_run {
+            AwsConfig::upsert(&profile_name, &profile, profiles)?;
+        }
+    }
+
+    if prune {
+        let mut stale: Vec<String> = profiles
+            .iter()
+            .filter(|(key, profile)| {
+                profile.azure_discovered_from.as_deref() == Some(source_name.as_str())
+                    && !generated.contains(*key)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        stale.sort();
+
+        for key in stale {
+            info!("Pruning {}", key.trim_start_matches("profile "));
+            if !dry_run {
+                let _ = profiles.remove(&key);
+            }
+        }
+    }
+
+    if dry_run {
+        info!("Dry run, the AWS config file was not modified");
+    } else {
+        AwsConfig::write(profiles)?;
+    }
+
+    Ok(())
+}
+
+fn render_profile_name(
+    template: &str,
+    role: &Role,
+    account_alias: &str,
+    source_profile: &str,
+) -> String {
+    let name = template
+        .replace("{account_id}", role.account_id())
+        .replace("{account_alias}", account_alias)
+        .replace("{role_name}", role.role_name())
+        .replace("{profile}", source_profile);
+
+    // Profile names end up in ini section headers, so keep them to a single word
+    name.split_whitespace().collect::<Vec<_>>().join("-")
+}
@@ -2,9 +2,13 @@ use crate::json::JsonCredential;
 use clap::Parser;
 use file_manager::aws_config::AwsConfig;
 use file_manager::aws_credential::AwsCredential;
+use shared::args::Command;
+use sso::inspect::Inspection;
+use sso::saml_response::decryption::DecryptionKey;
 use tracing_subscriber::EnvFilter;
 
 mod config;
+mod discover;
 mod json;
 
 /// Required due to using the stderr writer vs no writer specified
@@ -17,7 +21,10 @@ macro_rules! init_tracing {
         logging
             .with_target($debug)
             .with_line_number($debug)
-            .with_env_filter(EnvFilter::from("headless_chrome=off,tungstenite=off"))
+            // Progress from this workspace is logged at info, dependencies only warn
+            .with_env_filter(EnvFilter::from(
+                "warn,aws_azure_login=info,sso=info,file_manager=info,headless_chrome=off,tungstenite=off",
+            ))
             .init();
     };
 }
@@ -26,7 +33,13 @@ macro_rules! init_tracing {
 async fn main() -> anyhow::Result<()> {
     let args = shared::args::Args::parse();
 
-    if args.json {
+    let saml_out = match &args.command {
+        Some(Command::Saml { out }) => Some(out.clone()),
+        _ => args.saml_out.clone(),
+    };
+
+    // Keep stdout clean when it carries credentials or the SAML response
+    if args.json || saml_out.as_deref() == Some("-") {
         let logging = tracing_subscriber::fmt().with_writer(std::io::stderr);
         init_tracing!(logging, args.debug);
     } else {
@@ -39,6 +52,67 @@ async fn main() -> anyhow::Result<()> {
         .clone()
         .unwrap_or_else(|| std::env::var("AWS_PROFILE").unwrap_or("default".to_string()));
 
+    if let Some(Command::Import {
+        metadata,
+        app_id_uri,
+        region,
+        verify,
+    }) = &args.command
+    {
+        let mut configs = AwsConfig::read_file().unwrap_or_default();
+        config::import_metadata(
+            &mut configs,
+            &profile_name,
+            metadata,
+            app_id_uri.as_deref(),
+            region.as_deref(),
+            *verify,
+        )?;
+        return Ok(());
+    }
+
+    if let Some(Command::Inspect { input }) = &args.command {
+        let configs = AwsConfig::read_file().unwrap_or_default();
+        let decryption_key = match AwsConfig::get(&profile_name, &configs) {
+            Ok(config) => DecryptionKey::from_config(&config)?,
+            Err(_) => None,
+        };
+
+        let inspection = match input {
+            Some(input) => Inspection::from_input(input, decryption_key.as_ref())?,
+            None => Inspection::from_saml_response(
+                &sso::sso::fetch_saml(&configs, &profile_name, &args)?,
+                decryption_key.as_ref(),
+            )?,
+        };
+
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&inspection)?);
+        } else {
+            println!("{}", inspection);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Discover {
+        template,
+        dry_run,
+        prune,
+    }) = &args.command
+    {
+        let mut configs = AwsConfig::read_file()?;
+        let roles = sso::sso::fetch_roles(&configs, &profile_name, &args)?;
+        discover::discover_profiles(
+            &mut configs,
+            &profile_name,
+            &roles,
+            template,
+            *dry_run,
+            *prune,
+        )?;
+        return Ok(());
+    }
+
     if args.configure {
         let mut configs = AwsConfig::read_file().unwrap_or_default();
         config::configure_profile(&mut configs, &profile_name)?;
@@ -46,6 +120,11 @@ async fn main() -> anyhow::Result<()> {
     }
 
     let configs = AwsConfig::read_file()?;
+
+    if let Some(path) = saml_out {
+        sso::sso::export_saml(&configs, &profile_name, &args, &path)?;
+        return Ok(());
+    }
     let mut credentials = AwsCredential::read_file().unwrap_or_default();
 
     if args.all {
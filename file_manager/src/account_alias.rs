@@ -0,0 +1,43 @@
+use anyhow::{anyhow, Result};
+use directories::UserDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+/// Friendly name for an AWS account, keyed by account ID
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AccountAlias {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+}
+
+impl AccountAlias {
+    fn file_path() -> Result<PathBuf> {
+        match UserDirs::new() {
+            Some(user_dirs) => Ok(user_dirs.home_dir().join(".aws/azure_login_accounts")),
+            None => Err(anyhow!("Unable to get user directories")),
+        }
+    }
+
+    pub fn read_file() -> Result<HashMap<String, AccountAlias>> {
+        let aliases_path = Self::file_path()?;
+        if !aliases_path.exists() {
+            return Ok(HashMap::new());
+        }
+        let file = File::open(aliases_path)?;
+        let reader = BufReader::new(file);
+        let aliases: HashMap<String, AccountAlias> = serde_ini::from_bufread(reader)?;
+
+        Ok(aliases)
+    }
+
+    /// Aliases by account ID, accounts without one are left out
+    pub fn read_aliases() -> Result<HashMap<String, String>> {
+        Ok(Self::read_file()?
+            .into_iter()
+            .filter_map(|(account_id, account)| Some((account_id, account.alias?)))
+            .collect())
+    }
+}
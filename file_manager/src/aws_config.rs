@@ -4,12 +4,13 @@
 use anyhow::{anyhow, Result};
 use directories::UserDirs;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tracing::warn;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct AwsConfig {
     // TODO: Possibly make a hash map
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -17,12 +18,22 @@ pub struct AwsConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub azure_app_id_uri: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_cloud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_authority_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_idp_initiated_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub azure_default_username: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub azure_default_password: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub azure_default_role_arn: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_fallback_roles: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_role_filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub azure_default_duration_hours: Option<u8>,
     #[serde(
         default,
@@ -31,9 +42,115 @@ pub struct AwsConfig {
         deserialize_with = "deserialize_string_to_bool"
     )]
     pub azure_default_remember_me: Option<bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub azure_force_authn: Option<bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub azure_is_passive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_name_id_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_authn_context: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_authn_context_comparison: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_acs_url: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub azure_regional_signin: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_sts_endpoint_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_relay_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_domain_hint: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub azure_select_account: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_idp_entity_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_signing_certificate: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub azure_verify_saml: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_saml_decryption_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_discovered_from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_chain_role_arn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_chain_external_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_chain_session_name: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub azure_chain_cache: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_session_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_session_policy_arns: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_session_tags: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_transitive_tag_keys: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_source_identity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azure_session_name_template: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub region: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub role_arn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role_session_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sts_regional_endpoints: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub use_fips_endpoint: Option<bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_bool_to_string",
+        deserialize_with = "deserialize_string_to_bool"
+    )]
+    pub use_dualstack_endpoint: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub okta_default_username: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub okta_default_password: Option<String>,
@@ -46,12 +163,50 @@ impl Default for AwsConfig {
         Self {
             azure_tenant_id: None,
             azure_app_id_uri: Some("https://signin.aws.amazon.com/saml".to_string()),
+            azure_cloud: None,
+            azure_authority_host: None,
+            azure_idp_initiated_url: None,
             azure_default_username: None,
             azure_default_password: None,
             azure_default_role_arn: None,
+            azure_fallback_roles: None,
+            azure_role_filter: None,
             azure_default_duration_hours: Some(8),
             azure_default_remember_me: Some(true),
+            azure_force_authn: None,
+            azure_is_passive: None,
+            azure_name_id_format: None,
+            azure_authn_context: None,
+            azure_authn_context_comparison: None,
+            azure_acs_url: None,
+            azure_regional_signin: None,
+            azure_sts_endpoint_url: None,
+            azure_relay_state: None,
+            azure_domain_hint: None,
+            azure_select_account: None,
+            azure_idp_entity_id: None,
+            azure_signing_certificate: None,
+            azure_verify_saml: None,
+            azure_saml_decryption_key: None,
+            azure_discovered_from: None,
+            azure_chain_role_arn: None,
+            azure_chain_external_id: None,
+            azure_chain_session_name: None,
+            azure_chain_cache: None,
+            azure_session_policy: None,
+            azure_session_policy_arns: None,
+            azure_session_tags: None,
+            azure_transitive_tag_keys: None,
+            azure_source_identity: None,
+            azure_session_name_template: None,
             region: Some("ap-southeast-2".to_string()),
+            role_arn: None,
+            source_profile: None,
+            external_id: None,
+            role_session_name: None,
+            sts_regional_endpoints: None,
+            use_fips_endpoint: None,
+            use_dualstack_endpoint: None,
             okta_default_username: None,
             okta_default_password: None,
             credential_process: None,
@@ -59,35 +214,105 @@ impl Default for AwsConfig {
     }
 }
 
+/// A profile as written, followed by the keys it had in the file that AwsConfig doesn't model
+#[derive(Serialize)]
+struct Section<'a> {
+    #[serde(flatten)]
+    profile: &'a AwsConfig,
+    #[serde(flatten)]
+    unmanaged: BTreeMap<String, String>,
+}
+
 impl AwsConfig {
     fn file_path() -> Result<PathBuf> {
+        let config_path = Self::default_file_path()?;
+        if config_path.exists() {
+            Ok(config_path)
+        } else {
+            Err(anyhow!(
+                "AWS config file not found, please run with -c or --configure"
+            ))
+        }
+    }
+
+    fn default_file_path() -> Result<PathBuf> {
         match UserDirs::new() {
-            Some(user_dirs) => {
-                let config_path = user_dirs.home_dir().join(".aws/config");
-                if config_path.exists() {
-                    Ok(config_path)
-                } else {
-                    Err(anyhow!(
-                        "AWS config file not found, please run with -c or --configure"
-                    ))
-                }
-            }
+            Some(user_dirs) => Ok(user_dirs.home_dir().join(".aws/config")),
             None => Err(anyhow!("Unable to get user directories")),
         }
     }
 
     pub fn read_file() -> Result<HashMap<String, AwsConfig>> {
-        let credentials_path = Self::file_path()?;
-        let file = File::open(credentials_path)?;
+        Self::read_from(&Self::file_path()?)
+    }
+
+    /// Writes the profiles, keeping the keys AwsConfig doesn't model (`sso_session`, `output`, ...) of every section
+    pub fn write(profiles: &HashMap<String, AwsConfig>) -> Result<()> {
+        let config_path = Self::default_file_path()?;
+        if let Some(directory) = config_path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        Self::write_to(profiles, config_path)
+    }
+
+    fn read_from(config_path: &Path) -> Result<HashMap<String, AwsConfig>> {
+        let file = File::open(config_path)?;
         let reader = BufReader::new(file);
-        let aws_credentials: HashMap<String, AwsConfig> = serde_ini::from_bufread(reader)?;
+        let profiles: HashMap<String, AwsConfig> = serde_ini::from_bufread(reader)?;
 
-        Ok(aws_credentials)
+        Ok(profiles)
     }
 
-    pub fn write(profiles: &HashMap<String, AwsConfig>) -> Result<()> {
-        let credentials_path = Self::file_path()?;
-        serialize_write_ordered(profiles, credentials_path, FileName::Config)
+    fn write_to(profiles: &HashMap<String, AwsConfig>, config_path: PathBuf) -> Result<()> {
+        let mut unmanaged = if config_path.exists() {
+            Self::unmanaged_keys(&config_path).unwrap_or_else(|error| {
+                warn!(
+                    "Unable to read the settings aws-azure-login doesn't manage, they will be dropped: {}",
+                    error
+                );
+                HashMap::new()
+            })
+        } else {
+            HashMap::new()
+        };
+
+        let sections: HashMap<String, Section> = profiles
+            .iter()
+            .map(|(name, profile)| {
+                let section = Section {
+                    profile,
+                    unmanaged: unmanaged.remove(name).unwrap_or_default(),
+                };
+                (name.clone(), section)
+            })
+            .collect();
+
+        serialize_write_ordered(&sections, config_path, FileName::Config)
+    }
+
+    /// The keys of each section that are lost when it is read into an AwsConfig
+    fn unmanaged_keys(config_path: &Path) -> Result<HashMap<String, BTreeMap<String, String>>> {
+        let file = File::open(config_path)?;
+        let sections: HashMap<String, HashMap<String, String>> =
+            serde_ini::from_bufread(BufReader::new(file))?;
+        let profiles = Self::read_from(config_path)?;
+
+        let mut unmanaged = HashMap::new();
+        for (section, keys) in sections {
+            let managed: HashMap<String, String> = match profiles.get(&section) {
+                Some(profile) => serde_ini::from_str(&serde_ini::to_string(profile)?)?,
+                None => HashMap::new(),
+            };
+            let keys: BTreeMap<String, String> = keys
+                .into_iter()
+                .filter(|(key, _)| !managed.contains_key(key))
+                .collect();
+            if !keys.is_empty() {
+                let _ = unmanaged.insert(section, keys);
+            }
+        }
+
+        Ok(unmanaged)
     }
 
     pub fn get(profile_name: &str, profiles: &HashMap<String, AwsConfig>) -> Result<AwsConfig> {
@@ -122,3 +347,74 @@ impl AwsConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+[profile work]
+azure_tenant_id=11111111-2222-3333-4444-555555555555
+credential_process=aws-azure-login --json
+output=json
+region=us-east-1
+sso_session=corp
+
+[profile gone]
+cli_pager=
+
+[sso-session corp]
+sso_start_url=https://corp.awsapps.com/start
+";
+
+    #[test]
+    fn write_keeps_unmanaged_keys() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join("config");
+        std::fs::write(&config_path, CONFIG).unwrap();
+
+        let mut profiles = AwsConfig::read_from(&config_path).unwrap();
+        profiles.get_mut("profile work").unwrap().credential_process = None;
+        let _ = profiles.remove("profile gone");
+        AwsConfig::write_to(&profiles, config_path.clone()).unwrap();
+
+        // serde_ini ends key lines with CRLF
+        let written = std::fs::read_to_string(&config_path)
+            .unwrap()
+            .replace("\r\n", "\n");
+        assert_eq!(
+            written,
+            "\
+[profile work]
+azure_tenant_id=11111111-2222-3333-4444-555555555555
+region=us-east-1
+output=json
+sso_session=corp
+
+[sso-session corp]
+sso_start_url=https://corp.awsapps.com/start
+
+"
+        );
+    }
+
+    #[test]
+    fn unmanaged_keys_by_section() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join("config");
+        std::fs::write(&config_path, CONFIG).unwrap();
+
+        let unmanaged = AwsConfig::unmanaged_keys(&config_path).unwrap();
+
+        assert_eq!(unmanaged.len(), 3);
+        assert_eq!(
+            unmanaged["profile work"].keys().collect::<Vec<_>>(),
+            ["output", "sso_session"]
+        );
+        assert_eq!(
+            unmanaged["sso-session corp"]["sso_start_url"],
+            "https://corp.awsapps.com/start"
+        );
+        assert_eq!(unmanaged["profile gone"]["cli_pager"], "");
+    }
+}
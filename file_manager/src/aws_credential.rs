@@ -1,3 +1,4 @@
+use crate::clock_skew::ClockSkew;
 use crate::serde_support::{serialize_write_ordered, FileName};
 use anyhow::{anyhow, bail, Result};
 use chrono::{DateTime, Utc};
@@ -71,12 +72,14 @@ impl AwsCredential {
         profile_name: &str,
         profiles: &HashMap<String, AwsCredential>,
     ) -> Result<AwsCredential> {
-        let profile = profiles.get(profile_name).ok_or_else(|| {
-            anyhow!(
-                "Profile '{}' not found in the AWS credentials file",
-                profile_name
-            )
-        })?;
+        let profile = profiles
+            .get(&Self::profile_key(profile_name))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Profile '{}' not found in the AWS credentials file",
+                    profile_name
+                )
+            })?;
 
         Ok(profile.clone())
     }
@@ -84,7 +87,7 @@ impl AwsCredential {
     pub fn is_profile_about_to_expire(&self) -> bool {
         match self.aws_expiration {
             Some(expiration_date) => {
-                let time_difference = expiration_date.signed_duration_since(Utc::now());
+                let time_difference = expiration_date.signed_duration_since(ClockSkew::now());
                 time_difference < chrono::Duration::minutes(11)
             }
             None => true,
@@ -96,8 +99,16 @@ impl AwsCredential {
         profile: &AwsCredential,
         profiles: &mut HashMap<String, AwsCredential>,
     ) -> Result<()> {
-        let _ = profiles.insert(profile_name.to_owned(), profile.to_owned());
+        let _ = profiles.insert(Self::profile_key(profile_name), profile.to_owned());
 
         Ok(())
     }
+
+    /// Credentials file sections are bare profile names, unlike the `profile ` prefixed config sections
+    pub fn profile_key(profile_name: &str) -> String {
+        profile_name
+            .strip_prefix("profile ")
+            .unwrap_or(profile_name)
+            .to_string()
+    }
 }
@@ -0,0 +1,147 @@
+use crate::aws_credential::AwsCredential;
+use crate::serde_support::{serialize_write_ordered, FileName};
+use anyhow::{anyhow, Result};
+use directories::UserDirs;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Credentials of the SAML role a profile chains from, keyed by profile name
+pub struct ChainCache;
+
+impl ChainCache {
+    fn file_path() -> Result<PathBuf> {
+        match UserDirs::new() {
+            Some(user_dirs) => Ok(user_dirs.home_dir().join(".aws/azure_login_chain_cache")),
+            None => Err(anyhow!("Unable to get user directories")),
+        }
+    }
+
+    pub fn read_file() -> Result<HashMap<String, AwsCredential>> {
+        Self::read_from(&Self::file_path()?)
+    }
+
+    pub fn write(credentials: &HashMap<String, AwsCredential>) -> Result<()> {
+        Self::write_to(credentials, Self::file_path()?)
+    }
+
+    /// The cached credential, if it is not about to expire
+    pub fn get(profile_name: &str) -> Option<AwsCredential> {
+        Self::get_from(&Self::file_path().ok()?, profile_name)
+    }
+
+    pub fn upsert(profile_name: &str, credential: &AwsCredential) -> Result<()> {
+        Self::upsert_into(Self::file_path()?, profile_name, credential)
+    }
+
+    fn read_from(cache_path: &Path) -> Result<HashMap<String, AwsCredential>> {
+        if !cache_path.exists() {
+            return Ok(HashMap::new());
+        }
+        let file = File::open(cache_path)?;
+        let reader = BufReader::new(file);
+        let credentials: HashMap<String, AwsCredential> = serde_ini::from_bufread(reader)?;
+
+        Ok(credentials)
+    }
+
+    fn write_to(credentials: &HashMap<String, AwsCredential>, cache_path: PathBuf) -> Result<()> {
+        serialize_write_ordered(credentials, cache_path, FileName::ChainCache)
+    }
+
+    fn get_from(cache_path: &Path, profile_name: &str) -> Option<AwsCredential> {
+        Self::read_from(cache_path)
+            .ok()?
+            .get(profile_name)
+            .filter(|credential| !credential.is_profile_about_to_expire())
+            .cloned()
+    }
+
+    fn upsert_into(
+        cache_path: PathBuf,
+        profile_name: &str,
+        credential: &AwsCredential,
+    ) -> Result<()> {
+        let mut credentials = Self::read_from(&cache_path)?;
+        let _ = credentials.insert(profile_name.to_owned(), credential.to_owned());
+        Self::write_to(&credentials, cache_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    fn credential(access_key_id: &str, expires_in: Duration) -> AwsCredential {
+        AwsCredential {
+            profile_name: None,
+            aws_access_key_id: Some(access_key_id.to_string()),
+            aws_secret_access_key: Some("secret".to_string()),
+            aws_session_token: Some("token".to_string()),
+            aws_expiration: Some(Utc::now() + expires_in),
+        }
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache_path = directory.path().join("azure_login_chain_cache");
+
+        assert!(ChainCache::read_from(&cache_path).unwrap().is_empty());
+        assert!(ChainCache::get_from(&cache_path, "profile dev").is_none());
+    }
+
+    #[test]
+    fn upsert_round_trips() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache_path = directory.path().join("azure_login_chain_cache");
+
+        ChainCache::upsert_into(
+            cache_path.clone(),
+            "profile dev",
+            &credential("AKIADEV", Duration::hours(1)),
+        )
+        .unwrap();
+        ChainCache::upsert_into(
+            cache_path.clone(),
+            "profile prod",
+            &credential("AKIAPROD", Duration::hours(1)),
+        )
+        .unwrap();
+        ChainCache::upsert_into(
+            cache_path.clone(),
+            "profile dev",
+            &credential("AKIADEV2", Duration::hours(1)),
+        )
+        .unwrap();
+
+        let dev = ChainCache::get_from(&cache_path, "profile dev").unwrap();
+        assert_eq!(dev.aws_access_key_id.as_deref(), Some("AKIADEV2"));
+        assert_eq!(dev.aws_session_token.as_deref(), Some("token"));
+        let prod = ChainCache::get_from(&cache_path, "profile prod").unwrap();
+        assert_eq!(prod.aws_access_key_id.as_deref(), Some("AKIAPROD"));
+        assert!(ChainCache::get_from(&cache_path, "profile test").is_none());
+    }
+
+    #[test]
+    fn skips_credentials_about_to_expire() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache_path = directory.path().join("azure_login_chain_cache");
+
+        ChainCache::upsert_into(
+            cache_path.clone(),
+            "profile dev",
+            &credential("AKIADEV", Duration::minutes(5)),
+        )
+        .unwrap();
+        let mut expired = credential("AKIAPROD", Duration::hours(1));
+        expired.aws_expiration = None;
+        ChainCache::upsert_into(cache_path.clone(), "profile prod", &expired).unwrap();
+
+        assert!(ChainCache::get_from(&cache_path, "profile dev").is_none());
+        assert!(ChainCache::get_from(&cache_path, "profile prod").is_none());
+        assert_eq!(ChainCache::read_from(&cache_path).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::serde_support::{serialize_write_ordered, FileName};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use directories::UserDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const SECTION: &str = "clock";
+
+/// Offsets measured more than this long ago are not trusted, the clock may have been fixed since
+const MAX_AGE_HOURS: i64 = 24;
+
+/// Offsets within this many seconds of the remembered one are not written again
+const WRITE_THRESHOLD_SECONDS: i64 = 2;
+
+static OFFSET_SECONDS: Mutex<Option<i64>> = Mutex::new(None);
+
+/// How far the local clock is from the clocks of Azure AD and STS, as last measured
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ClockSkew {
+    /// Seconds to add to the local clock to get server time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset_seconds: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measured_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl ClockSkew {
+    fn file_path() -> Result<PathBuf> {
+        match UserDirs::new() {
+            Some(user_dirs) => Ok(user_dirs.home_dir().join(".aws/azure_login_clock")),
+            None => Err(anyhow!("Unable to get user directories")),
+        }
+    }
+
+    pub fn read_file() -> Result<HashMap<String, ClockSkew>> {
+        let state_path = Self::file_path()?;
+        if !state_path.exists() {
+            return Ok(HashMap::new());
+        }
+        let file = File::open(state_path)?;
+        let reader = BufReader::new(file);
+        let clock_skews: HashMap<String, ClockSkew> = serde_ini::from_bufread(reader)?;
+
+        Ok(clock_skews)
+    }
+
+    pub fn write(clock_skews: &HashMap<String, ClockSkew>) -> Result<()> {
+        let state_path = Self::file_path()?;
+        serialize_write_ordered(clock_skews, state_path, FileName::ClockSkew)
+    }
+
+    pub fn get() -> Option<ClockSkew> {
+        Self::read_file().ok()?.get(SECTION).cloned()
+    }
+
+    /// The measured offset, unless it was measured too long before `now` to trust
+    fn offset_at(&self, now: DateTime<Utc>) -> Option<i64> {
+        self.measured_at
+            .filter(|measured_at| now - *measured_at < Duration::hours(MAX_AGE_HOURS))
+            .and(self.offset_seconds)
+    }
+
+    /// The offset to apply to the local clock, loaded from the last recent measurement
+    pub fn offset() -> Duration {
+        let mut offset_seconds = OFFSET_SECONDS.lock().unwrap_or_else(|e| e.into_inner());
+        let seconds = *offset_seconds.get_or_insert_with(|| {
+            Self::get()
+                .and_then(|skew| skew.offset_at(Utc::now()))
+                .unwrap_or_default()
+        });
+
+        Duration::seconds(seconds)
+    }
+
+    /// The current time by the server's clock
+    pub fn now() -> DateTime<Utc> {
+        Utc::now() + Self::offset()
+    }
+
+    /// Records the time a server reported when the local clock read `local_time`, returning the previous and new offsets
+    pub fn record(
+        server_time: DateTime<Utc>,
+        local_time: DateTime<Utc>,
+        source: &str,
+    ) -> Result<(Duration, Duration)> {
+        let previous = Self::offset();
+        let offset = offset_between(server_time, local_time);
+
+        let mut offset_seconds = OFFSET_SECONDS.lock().unwrap_or_else(|e| e.into_inner());
+        *offset_seconds = Some(offset);
+
+        if is_new_offset(previous.num_seconds(), offset) {
+            let clock_skew = ClockSkew {
+                offset_seconds: Some(offset),
+                measured_at: Some(local_time),
+                source: Some(source.to_owned()),
+            };
+            let mut clock_skews = Self::read_file()?;
+            let _ = clock_skews.insert(SECTION.to_owned(), clock_skew);
+            Self::write(&clock_skews)?;
+        }
+
+        Ok((previous, Duration::seconds(offset)))
+    }
+}
+
+/// Seconds to add to `local_time` to get `server_time`
+fn offset_between(server_time: DateTime<Utc>, local_time: DateTime<Utc>) -> i64 {
+    (server_time - local_time).num_seconds()
+}
+
+fn is_new_offset(previous_seconds: i64, offset_seconds: i64) -> bool {
+    (offset_seconds - previous_seconds).abs() >= WRITE_THRESHOLD_SECONDS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skew(offset_seconds: i64, measured_at: DateTime<Utc>) -> ClockSkew {
+        ClockSkew {
+            offset_seconds: Some(offset_seconds),
+            measured_at: Some(measured_at),
+            source: Some("STS".to_string()),
+        }
+    }
+
+    #[test]
+    fn offset_corrects_the_local_clock() {
+        let local_time = Utc::now();
+
+        let behind = local_time + Duration::seconds(95);
+        let offset = offset_between(behind, local_time);
+        assert_eq!(offset, 95);
+        assert_eq!(local_time + Duration::seconds(offset), behind);
+
+        let ahead = local_time - Duration::minutes(10);
+        let offset = offset_between(ahead, local_time);
+        assert_eq!(offset, -600);
+        assert_eq!(local_time + Duration::seconds(offset), ahead);
+    }
+
+    #[test]
+    fn recent_offsets_are_trusted() {
+        let now = Utc::now();
+
+        assert_eq!(
+            skew(120, now - Duration::hours(1)).offset_at(now),
+            Some(120)
+        );
+        assert_eq!(
+            skew(-30, now - Duration::hours(MAX_AGE_HOURS - 1)).offset_at(now),
+            Some(-30)
+        );
+    }
+
+    #[test]
+    fn stale_or_incomplete_offsets_are_ignored() {
+        let now = Utc::now();
+
+        assert_eq!(
+            skew(120, now - Duration::hours(MAX_AGE_HOURS)).offset_at(now),
+            None
+        );
+        assert_eq!(ClockSkew::default().offset_at(now), None);
+        let unmeasured = ClockSkew {
+            measured_at: None,
+            ..skew(120, now)
+        };
+        assert_eq!(unmeasured.offset_at(now), None);
+    }
+
+    #[test]
+    fn only_changed_offsets_are_written() {
+        assert!(!is_new_offset(120, 120));
+        assert!(!is_new_offset(120, 121));
+        assert!(!is_new_offset(120, 119));
+        assert!(is_new_offset(120, 122));
+        assert!(is_new_offset(0, -2));
+    }
+}
@@ -1,3 +1,8 @@
+pub mod account_alias;
 pub mod aws_config;
 pub mod aws_credential;
+pub mod chain_cache;
+pub mod clock_skew;
+pub mod profile_state;
+pub mod role_state;
 pub mod serde_support;
@@ -0,0 +1,52 @@
+use crate::serde_support::{serialize_write_ordered, FileName};
+use anyhow::{anyhow, Result};
+use directories::UserDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+/// Choices remembered between logins, keyed by profile name
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProfileState {
+    /// Role ARN last picked interactively for the profile
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_role_arn: Option<String>,
+}
+
+impl ProfileState {
+    fn file_path() -> Result<PathBuf> {
+        match UserDirs::new() {
+            Some(user_dirs) => Ok(user_dirs.home_dir().join(".aws/azure_login_profiles")),
+            None => Err(anyhow!("Unable to get user directories")),
+        }
+    }
+
+    pub fn read_file() -> Result<HashMap<String, ProfileState>> {
+        let state_path = Self::file_path()?;
+        if !state_path.exists() {
+            return Ok(HashMap::new());
+        }
+        let file = File::open(state_path)?;
+        let reader = BufReader::new(file);
+        let profile_states: HashMap<String, ProfileState> = serde_ini::from_bufread(reader)?;
+
+        Ok(profile_states)
+    }
+
+    pub fn write(profile_states: &HashMap<String, ProfileState>) -> Result<()> {
+        let state_path = Self::file_path()?;
+        serialize_write_ordered(profile_states, state_path, FileName::ProfileState)
+    }
+
+    pub fn get(profile_name: &str) -> Option<ProfileState> {
+        Self::read_file().ok()?.get(profile_name).cloned()
+    }
+
+    pub fn upsert(profile_name: &str, profile_state: &ProfileState) -> Result<()> {
+        let mut profile_states = Self::read_file()?;
+        let _ = profile_states.insert(profile_name.to_owned(), profile_state.to_owned());
+        Self::write(&profile_states)
+    }
+}
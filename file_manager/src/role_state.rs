@@ -0,0 +1,69 @@
+use crate::serde_support::{serialize_write_ordered, FileName};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use directories::UserDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+/// Durations learnt longer ago than this are retried, the role's maximum may have been raised since
+const MAX_AGE_DAYS: i64 = 7;
+
+/// What has been learnt about a role from previous logins, keyed by role ARN
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RoleState {
+    /// Longest session duration in seconds STS has accepted for the role
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_session_duration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub learned_at: Option<DateTime<Utc>>,
+}
+
+impl RoleState {
+    fn file_path() -> Result<PathBuf> {
+        match UserDirs::new() {
+            Some(user_dirs) => Ok(user_dirs.home_dir().join(".aws/azure_login_roles")),
+            None => Err(anyhow!("Unable to get user directories")),
+        }
+    }
+
+    pub fn read_file() -> Result<HashMap<String, RoleState>> {
+        let state_path = Self::file_path()?;
+        if !state_path.exists() {
+            return Ok(HashMap::new());
+        }
+        let file = File::open(state_path)?;
+        let reader = BufReader::new(file);
+        let role_states: HashMap<String, RoleState> = serde_ini::from_bufread(reader)?;
+
+        Ok(role_states)
+    }
+
+    pub fn write(role_states: &HashMap<String, RoleState>) -> Result<()> {
+        let state_path = Self::file_path()?;
+        serialize_write_ordered(role_states, state_path, FileName::RoleState)
+    }
+
+    pub fn get(role_arn: &str) -> Option<RoleState> {
+        Self::read_file().ok()?.get(role_arn).cloned()
+    }
+
+    /// The longest accepted session duration, unless it was learnt too long ago to be trusted
+    pub fn max_session_duration(role_arn: &str) -> Option<u32> {
+        Self::get(role_arn)
+            .filter(|state| {
+                state.learned_at.is_some_and(|learned_at| {
+                    Utc::now() - learned_at < Duration::days(MAX_AGE_DAYS)
+                })
+            })
+            .and_then(|state| state.max_session_duration)
+    }
+
+    pub fn upsert(role_arn: &str, role_state: &RoleState) -> Result<()> {
+        let mut role_states = Self::read_file()?;
+        let _ = role_states.insert(role_arn.to_owned(), role_state.to_owned());
+        Self::write(&role_states)
+    }
+}
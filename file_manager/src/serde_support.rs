@@ -5,7 +5,7 @@ use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::PathBuf;
-use tracing::info;
+use tracing::{debug, info};
 
 pub fn serialize_bool_to_string<S>(value: &Option<bool>, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -34,6 +34,10 @@ where
 pub enum FileName {
     Config,
     Credentials,
+    RoleState,
+    ProfileState,
+    ChainCache,
+    ClockSkew,
 }
 
 impl Display for FileName {
@@ -41,6 +45,10 @@ impl Display for FileName {
         match self {
             FileName::Config => write!(f, "Config"),
             FileName::Credentials => write!(f, "Credentials"),
+            FileName::RoleState => write!(f, "Role State"),
+            FileName::ProfileState => write!(f, "Profile State"),
+            FileName::ChainCache => write!(f, "Chain Cache"),
+            FileName::ClockSkew => write!(f, "Clock Skew"),
         }
     }
 }
@@ -65,7 +73,11 @@ where
         writeln!(writer)?;
     }
 
-    info!("AWS {} file modified", file_name);
+    match file_name {
+        FileName::Config | FileName::Credentials => info!("AWS {} file modified", file_name),
+        // State files change on most logins, so they are only mentioned when debugging
+        _ => debug!("AWS {} file modified", file_name),
+    }
 
     Ok(())
 }
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
@@ -5,6 +7,10 @@ pub struct Args {
     #[arg(short, long)]
     pub profile: Option<String>,
 
+    /// The role to assume, as an ARN or <account alias or ID>/<role name>, overrides azure_default_role_arn
+    #[arg(short, long, conflicts_with = "all")]
+    pub role: Option<String>,
+
     /// Run for all configured profiles
     #[arg(short, long, default_value_t = false)]
     pub all: bool,
@@ -28,4 +34,61 @@ pub struct Args {
     /// Enables verbose logging to the console and viewing the browser automation
     #[arg(short, long, default_value_t = cfg!(debug_assertions))]
     pub debug: bool,
+
+    /// Use a base64 or XML SAML response from a file (or - for stdin) instead of the browser login
+    #[arg(long, value_name = "PATH", conflicts_with = "all")]
+    pub saml_file: Option<String>,
+
+    /// Only perform the browser login and write the base64 SAML response to a file (or - for stdout)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["all", "saml_file"])]
+    pub saml_out: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Configure the profile from an Azure FederationMetadata.xml or app SAML metadata file
+    Import {
+        /// Path to the federation metadata XML
+        metadata: PathBuf,
+
+        /// The App ID URI (Identifier) of the AWS enterprise application
+        #[arg(long)]
+        app_id_uri: Option<String>,
+
+        /// Region for the profile
+        #[arg(long)]
+        region: Option<String>,
+
+        /// Enable SAML signature verification with the imported certificates
+        #[arg(long, default_value_t = false)]
+        verify: bool,
+    },
+    /// Only perform the browser login and write the base64 SAML response, without assuming a role
+    Saml {
+        /// File to write the SAML response to, defaults to stdout
+        #[arg(short, long, value_name = "PATH", default_value = "-")]
+        out: String,
+    },
+    /// Log in once and generate a profile for every role in the assertion
+    Discover {
+        /// Profile name template, supports {account_id}, {account_alias}, {role_name} and {profile}
+        #[arg(short, long, default_value = "{account_alias}-{role_name}")]
+        template: String,
+
+        /// Show the profiles that would be written without changing the config
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+
+        /// Remove generated profiles whose role is no longer in the assertion
+        #[arg(long, default_value_t = false)]
+        prune: bool,
+    },
+    /// Decode and print a SAMLRequest URL or SAMLResponse, use --json for JSON output
+    Inspect {
+        /// Login URL with a SAMLRequest, or a file (or - for stdin) with a SAMLResponse, performs a login when omitted
+        input: Option<String>,
+    },
 }
@@ -0,0 +1,254 @@
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fmt::Display;
+
+static CODE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"AADSTS(\d+)(?::[ \t]*([^\r\n]*))?").unwrap());
+static REQUEST_ID_REGEX: Lazy<Regex> = Lazy::new(|| detail_regex("Request Id"));
+static CORRELATION_ID_REGEX: Lazy<Regex> = Lazy::new(|| detail_regex("Correlation Id"));
+static TIMESTAMP_REGEX: Lazy<Regex> = Lazy::new(|| detail_regex("Timestamp"));
+
+#[derive(Clone, Debug)]
+pub struct AzureAdError {
+    pub code: String,
+    pub message: Option<String>,
+    pub request_id: Option<String>,
+    pub correlation_id: Option<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl AzureAdError {
+    /// Extracts the AADSTS details from the visible text of an Azure AD sign in page
+    pub fn from_page_text(text: &str) -> Option<Self> {
+        let captures = CODE_REGEX.captures(text)?;
+
+        let code = format!("AADSTS{}", &captures[1]);
+        let message = captures
+            .get(2)
+            .map(|m| m.as_str().trim().to_string())
+            .filter(|m| !m.is_empty());
+
+        let timestamp = capture_detail(text, &TIMESTAMP_REGEX).and_then(|value| {
+            DateTime::parse_from_rfc3339(&value)
+                .map(|dt| dt.with_timezone(&Utc))
+                .ok()
+        });
+
+        Some(Self {
+            code,
+            message,
+            request_id: capture_detail(text, &REQUEST_ID_REGEX),
+            correlation_id: capture_detail(text, &CORRELATION_ID_REGEX),
+            timestamp,
+        })
+    }
+
+    /// Errors the user can resolve by completing a prompt in a visible browser, such as MFA
+    pub fn requires_interaction(&self) -> bool {
+        matches!(
+            self.code.as_str(),
+            "AADSTS50072" | "AADSTS50074" | "AADSTS50076" | "AADSTS50079" | "AADSTS50158"
+        )
+    }
+
+    pub fn explanation(&self) -> Option<&'static str> {
+        self.known().map(|(explanation, _)| explanation)
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        self.known().map(|(_, hint)| hint)
+    }
+
+    fn known(&self) -> Option<(&'static str, &'static str)> {
+        let known = match self.code.as_str() {
+            "AADSTS50105" => (
+                "You are not assigned to the AWS enterprise application",
+                "Ask your Azure AD admin to assign you (or one of your groups) to the AWS app",
+            ),
+            "AADSTS700016" => (
+                "The application was not found in the tenant",
+                "Check azure_app_id_uri and azure_tenant_id match the enterprise application",
+            ),
+            "AADSTS900971" => (
+                "No reply address was provided",
+                "Check the reply URL configured on the enterprise application",
+            ),
+            "AADSTS50011" => (
+                "The reply URL does not match the ones configured for the application",
+                "Add the AWS sign in URL for your region's partition to the app's reply URLs",
+            ),
+            "AADSTS75011" => (
+                "The authentication method does not match the one requested",
+                "Sign in again with the method requested by the application",
+            ),
+            "AADSTS53003" => (
+                "Access was blocked by Conditional Access policies",
+                "Contact your admin, the device or location does not satisfy the tenant's policies",
+            ),
+            "AADSTS53000" => (
+                "The device is not compliant with Conditional Access policies",
+                "Enroll or update the device in Intune, or sign in from a compliant device",
+            ),
+            "AADSTS50076" | "AADSTS50079" => (
+                "Multi-factor authentication is required",
+                "Complete MFA in the browser window that opens for it, or run with --debug to show it from the start",
+            ),
+            "AADSTS50055" => (
+                "Your password has expired",
+                "Reset your password, then update azure_default_password with --configure",
+            ),
+            "AADSTS50126" => (
+                "Invalid username or password",
+                "Check the credentials, then update them with --configure",
+            ),
+            "AADSTS50053" => (
+                "The account is locked after too many failed sign in attempts",
+                "Wait for the lockout to expire or ask your admin to unlock the account",
+            ),
+            "AADSTS50057" => (
+                "The user account is disabled",
+                "Ask your Azure AD admin to enable the account",
+            ),
+            "AADSTS50034" => (
+                "The user account does not exist in the tenant",
+                "Check azure_default_username and azure_tenant_id",
+            ),
+            "AADSTS90072" => (
+                "The account is from an external tenant and is not a guest of this tenant",
+                "Ask your admin to invite the account as a guest, or sign in with another account",
+            ),
+            "AADSTS700082" | "AADSTS50173" => (
+                "Your session has expired or been revoked",
+                "Run again with --force to sign in fresh",
+            ),
+            _ => return None,
+        };
+
+        Some(known)
+    }
+}
+
+fn detail_regex(label: &str) -> Regex {
+    Regex::new(&format!(r"(?i){}:?[ \t]*([^\s]+)", regex::escape(label))).unwrap()
+}
+
+fn capture_detail(text: &str, regex: &Regex) -> Option<String> {
+    regex
+        .captures(text)
+        .map(|captures| captures[1].trim().to_string())
+}
+
+impl Display for AzureAdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Azure AD rejected the login with {}", self.code)?;
+
+        if let Some(explanation) = self.explanation() {
+            write!(f, ": {}", explanation)?;
+        } else if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+
+        if let Some(hint) = self.hint() {
+            write!(f, "\nHint: {}", hint)?;
+        }
+
+        if let Some(request_id) = &self.request_id {
+            write!(f, "\nRequest ID: {}", request_id)?;
+        }
+
+        if let Some(correlation_id) = &self.correlation_id {
+            write!(f, "\nCorrelation ID: {}", correlation_id)?;
+        }
+
+        if let Some(timestamp) = &self.timestamp {
+            write!(f, "\nTimestamp: {}", timestamp.format("%Y-%m-%dT%H:%M:%SZ"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for AzureAdError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_TEXT: &str = "Sign in\n\
+        Sorry, but we're having trouble signing you in.\n\
+        AADSTS50105: Your administrator has configured the application AWS to block users unless they are specifically granted access.\n\
+        Troubleshooting details\n\
+        Request Id: 1b9a4c2e-7f1d-4c8e-9a55-2b0c0a7e3d01\n\
+        Correlation Id: 5e2f8d3a-0c4b-4b6e-8f1a-9d7c6b5a4e32\n\
+        Timestamp: 2023-09-14T03:12:45Z\n\
+        Message: AADSTS50105\n";
+
+    #[test]
+    fn parses_error_page() {
+        let error = AzureAdError::from_page_text(PAGE_TEXT).unwrap();
+
+        assert_eq!(error.code, "AADSTS50105");
+        assert!(error
+            .message
+            .as_deref()
+            .unwrap()
+            .starts_with("Your administrator has configured"));
+        assert_eq!(
+            error.request_id.as_deref(),
+            Some("1b9a4c2e-7f1d-4c8e-9a55-2b0c0a7e3d01")
+        );
+        assert_eq!(
+            error.correlation_id.as_deref(),
+            Some("5e2f8d3a-0c4b-4b6e-8f1a-9d7c6b5a4e32")
+        );
+        assert_eq!(
+            error.timestamp,
+            DateTime::parse_from_rfc3339("2023-09-14T03:12:45Z")
+                .ok()
+                .map(|dt| dt.with_timezone(&Utc))
+        );
+        assert!(error.hint().is_some());
+    }
+
+    #[test]
+    fn unknown_code_keeps_page_message() {
+        let error = AzureAdError::from_page_text("AADSTS12345: Something else went wrong").unwrap();
+
+        assert_eq!(error.code, "AADSTS12345");
+        assert!(error.explanation().is_none());
+        assert!(error
+            .to_string()
+            .contains("AADSTS12345: Something else went wrong"));
+    }
+
+    #[test]
+    fn display_includes_the_troubleshooting_details() {
+        let error = AzureAdError::from_page_text(PAGE_TEXT).unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "Azure AD rejected the login with AADSTS50105: You are not assigned to the AWS enterprise application\n\
+             Hint: Ask your Azure AD admin to assign you (or one of your groups) to the AWS app\n\
+             Request ID: 1b9a4c2e-7f1d-4c8e-9a55-2b0c0a7e3d01\n\
+             Correlation ID: 5e2f8d3a-0c4b-4b6e-8f1a-9d7c6b5a4e32\n\
+             Timestamp: 2023-09-14T03:12:45Z"
+        );
+    }
+
+    #[test]
+    fn mfa_errors_require_interaction() {
+        for code in ["AADSTS50076", "AADSTS50079"] {
+            let error = AzureAdError::from_page_text(&format!("{}: MFA required", code)).unwrap();
+            assert!(error.requires_interaction(), "{}", code);
+        }
+
+        let error = AzureAdError::from_page_text(PAGE_TEXT).unwrap();
+        assert!(!error.requires_interaction());
+    }
+
+    #[test]
+    fn ignores_pages_without_a_code() {
+        assert!(AzureAdError::from_page_text("Stay signed in?").is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::helpers::format_duration;
+use aws_sdk_sts::config::{Interceptor, RuntimeComponents};
+use aws_smithy_async::time::TimeSource;
+use aws_smithy_runtime_api::box_error::BoxError;
+use aws_smithy_runtime_api::client::interceptors::context::BeforeDeserializationInterceptorContextRef;
+use aws_smithy_types::config_bag::ConfigBag;
+use chrono::{DateTime, Duration, Utc};
+use file_manager::clock_skew::ClockSkew;
+use log::warn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+/// Skew STS and SAML validity checks start to trip over, warned about once per run
+const SIGNIFICANT_SKEW_SECONDS: i64 = 60;
+
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Records the time a server reported at `local_time`, warning when the local clock is significantly off
+pub fn observe_server_time(server_time: DateTime<Utc>, local_time: DateTime<Utc>, source: &str) {
+    let offset = match ClockSkew::record(server_time, local_time, source) {
+        Ok((_, offset)) => offset,
+        Err(error) => {
+            warn!("Unable to remember the clock skew: {}", error);
+            return;
+        }
+    };
+
+    if let Some(warning) = skew_warning(offset, source) {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            warn!("{}", warning);
+        }
+    }
+}
+
+/// Describes an offset large enough to trip validity checks
+fn skew_warning(offset: Duration, source: &str) -> Option<String> {
+    let seconds = offset.num_seconds();
+    if seconds.abs() < SIGNIFICANT_SKEW_SECONDS {
+        return None;
+    }
+
+    Some(format!(
+        "The local clock is {} {} {}, times are corrected for it but the system clock should be synced",
+        format_duration(seconds.abs()),
+        if seconds > 0 { "behind" } else { "ahead of" },
+        source
+    ))
+}
+
+/// Signs requests with the corrected clock, so STS doesn't reject them as expired
+#[derive(Debug)]
+pub struct CorrectedTimeSource;
+
+impl TimeSource for CorrectedTimeSource {
+    fn now(&self) -> SystemTime {
+        ClockSkew::now().into()
+    }
+}
+
+/// Measures the skew from the `Date` header of every STS response
+#[derive(Debug)]
+pub struct ServerDateInterceptor;
+
+impl Interceptor for ServerDateInterceptor {
+    fn name(&self) -> &'static str {
+        "ServerDateInterceptor"
+    }
+
+    fn read_after_transmit(
+        &self,
+        context: &BeforeDeserializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let server_time = context
+            .response()
+            .headers()
+            .get("date")
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+
+        if let Some(server_time) = server_time {
+            observe_server_time(server_time.with_timezone(&Utc), Utc::now(), "STS");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_skew_is_not_significant() {
+        assert_eq!(skew_warning(Duration::zero(), "STS"), None);
+        assert_eq!(skew_warning(Duration::seconds(59), "STS"), None);
+        assert_eq!(skew_warning(Duration::seconds(-59), "STS"), None);
+    }
+
+    #[test]
+    fn warns_when_behind() {
+        assert_eq!(
+            skew_warning(Duration::seconds(90), "Azure AD").unwrap(),
+            "The local clock is 1m behind Azure AD, times are corrected for it but the system clock should be synced"
+        );
+    }
+
+    #[test]
+    fn warns_when_ahead() {
+        assert_eq!(
+            skew_warning(Duration::seconds(-3 * 3600 - 600), "STS").unwrap(),
+            "The local clock is 3h 10m ahead of STS, times are corrected for it but the system clock should be synced"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use crate::partition::AwsPartition;
+use anyhow::{anyhow, bail, Result};
+use file_manager::aws_config::AwsConfig;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AzureCloud {
+    Public,
+    UsGovernment,
+    China,
+}
+
+impl AzureCloud {
+    pub fn authority_host(&self) -> &'static str {
+        match self {
+            AzureCloud::Public => "login.microsoftonline.com",
+            AzureCloud::UsGovernment => "login.microsoftonline.us",
+            AzureCloud::China => "login.chinacloudapi.cn",
+        }
+    }
+
+    pub fn from_partition(partition: AwsPartition) -> Self {
+        match partition {
+            AwsPartition::Aws => AzureCloud::Public,
+            AwsPartition::AwsUsGov => AzureCloud::UsGovernment,
+            AwsPartition::AwsCn => AzureCloud::China,
+        }
+    }
+
+    /// Infers the cloud from the AWS partition of the profile when `azure_cloud` is not set
+    pub fn from_config(config: &AwsConfig) -> Result<Self> {
+        match &config.azure_cloud {
+            Some(cloud) => cloud.parse(),
+            None => Ok(Self::from_partition(AwsPartition::from_config(config))),
+        }
+    }
+}
+
+impl FromStr for AzureCloud {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "public" | "azurepublic" | "azurecloud" => Ok(AzureCloud::Public),
+            "usgov" | "usgovernment" | "azureusgovernment" => Ok(AzureCloud::UsGovernment),
+            "china" | "azurechina" | "azurechinacloud" => Ok(AzureCloud::China),
+            _ => bail!(
+                "Unknown azure_cloud '{}', expected public, usgovernment or china",
+                s
+            ),
+        }
+    }
+}
+
+impl Display for AzureCloud {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AzureCloud::Public => write!(f, "public"),
+            AzureCloud::UsGovernment => write!(f, "usgovernment"),
+            AzureCloud::China => write!(f, "china"),
+        }
+    }
+}
+
+/// Resolves the base URL of the Azure AD authority, `AZURE_AUTHORITY_HOST` then `azure_authority_host` take precedence over the cloud
+pub fn authority_base_url(config: &AwsConfig) -> Result<String> {
+    authority_base_url_with_env(config, |name| std::env::var(name).ok())
+}
+
+fn authority_base_url_with_env(
+    config: &AwsConfig,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+    let host = match env("AZURE_AUTHORITY_HOST")
+        .filter(|host| !host.trim().is_empty())
+        .or_else(|| config.azure_authority_host.clone())
+    {
+        Some(host) if !host.trim().is_empty() => host.trim().to_string(),
+        _ => AzureCloud::from_config(config)?
+            .authority_host()
+            .to_string(),
+    };
+
+    let base_url = if host.contains("://") {
+        host
+    } else {
+        format!("https://{}", host)
+    };
+
+    Ok(base_url.trim_end_matches('/').to_string())
+}
+
+pub fn saml_endpoint(config: &AwsConfig) -> Result<String> {
+    Ok(format!(
+        "{}/{}/saml2",
+        authority_base_url(config)?,
+        config
+            .azure_tenant_id
+            .as_ref()
+            .ok_or(anyhow!("azure_tenant_id not set"))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn env_authority_host_wins_over_the_profile() {
+        let config = AwsConfig {
+            azure_authority_host: Some("login.partner.example".to_string()),
+            ..AwsConfig::default()
+        };
+        let env = |name: &str| {
+            (name == "AZURE_AUTHORITY_HOST").then(|| "https://login.env.example/".to_string())
+        };
+
+        assert_eq!(
+            authority_base_url_with_env(&config, env).unwrap(),
+            "https://login.env.example"
+        );
+        assert_eq!(
+            authority_base_url_with_env(&config, no_env).unwrap(),
+            "https://login.partner.example"
+        );
+    }
+
+    #[test]
+    fn blank_authority_hosts_fall_back_to_the_cloud() {
+        let config = AwsConfig {
+            azure_authority_host: Some(" ".to_string()),
+            azure_cloud: Some("usgovernment".to_string()),
+            ..AwsConfig::default()
+        };
+        let env = |_: &str| Some(String::new());
+
+        assert_eq!(
+            authority_base_url_with_env(&config, env).unwrap(),
+            "https://login.microsoftonline.us"
+        );
+    }
+
+    #[test]
+    fn parses_cloud_names() {
+        for name in ["public", "AzurePublic", " AzureCloud "] {
+            assert_eq!(name.parse::<AzureCloud>().unwrap(), AzureCloud::Public);
+        }
+        for name in ["usgov", "USGovernment", "AzureUSGovernment"] {
+            assert_eq!(
+                name.parse::<AzureCloud>().unwrap(),
+                AzureCloud::UsGovernment
+            );
+        }
+        for name in ["china", "AzureChina", "AzureChinaCloud"] {
+            assert_eq!(name.parse::<AzureCloud>().unwrap(), AzureCloud::China);
+        }
+
+        assert_eq!(
+            "germany".parse::<AzureCloud>().unwrap_err().to_string(),
+            "Unknown azure_cloud 'germany', expected public, usgovernment or china"
+        );
+    }
+
+    #[test]
+    fn infers_the_cloud_from_the_partition() {
+        let config = |role_arn: Option<&str>, region: &str| AwsConfig {
+            azure_default_role_arn: role_arn.map(str::to_string),
+            region: Some(region.to_string()),
+            ..AwsConfig::default()
+        };
+
+        assert_eq!(
+            AzureCloud::from_config(&config(None, "us-east-1")).unwrap(),
+            AzureCloud::Public
+        );
+        assert_eq!(
+            AzureCloud::from_config(&config(None, "us-gov-west-1")).unwrap(),
+            AzureCloud::UsGovernment
+        );
+        assert_eq!(
+            AzureCloud::from_config(&config(
+                Some("arn:aws-cn:iam::123456789012:role/Admin"),
+                "us-east-1"
+            ))
+            .unwrap(),
+            AzureCloud::China
+        );
+
+        let explicit = AwsConfig {
+            azure_cloud: Some("public".to_string()),
+            ..config(None, "us-gov-west-1")
+        };
+        assert_eq!(
+            AzureCloud::from_config(&explicit).unwrap(),
+            AzureCloud::Public
+        );
+    }
+
+    #[test]
+    fn authority_base_url_adds_https_only_without_a_scheme() {
+        let base_url = |host: &str| {
+            let config = AwsConfig {
+                azure_authority_host: Some(host.to_string()),
+                ..AwsConfig::default()
+            };
+            authority_base_url_with_env(&config, no_env).unwrap()
+        };
+
+        assert_eq!(
+            base_url("login.microsoftonline.us"),
+            "https://login.microsoftonline.us"
+        );
+        assert_eq!(
+            base_url(" http://localhost:8080/ "),
+            "http://localhost:8080"
+        );
+        assert_eq!(
+            base_url("https://login.chinacloudapi.cn/"),
+            "https://login.chinacloudapi.cn"
+        );
+    }
+
+    #[test]
+    fn saml_endpoint_needs_a_tenant() {
+        assert_eq!(
+            saml_endpoint(&AwsConfig::default())
+                .unwrap_err()
+                .to_string(),
+            "azure_tenant_id not set"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::saml_response::verification::SigningCertificate;
+use anyhow::{anyhow, bail, Result};
+use directories::UserDirs;
+use serde::Deserialize;
+use serde_xml_rs::from_str;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const HTTP_REDIRECT_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+
+#[derive(Clone, Debug)]
+pub struct FederationMetadata {
+    pub entity_id: String,
+    pub tenant_id: Option<String>,
+    pub login_endpoint: Option<String>,
+    pub signing_certificates: Vec<SigningCertificate>,
+}
+
+impl FederationMetadata {
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        let entity: EntityDescriptorXml = from_str(xml)?;
+        let idp = entity.idp_sso_descriptor.ok_or(anyhow!(
+            "No IDPSSODescriptor found in the federation metadata"
+        ))?;
+
+        let signing_certificates = idp
+            .key_descriptor
+            .iter()
+            .filter(|key| key.key_use.as_deref().unwrap_or("signing") == "signing")
+            .filter_map(|key| key.key_info.x509_data.x509_certificate.as_deref())
+            .map(SigningCertificate::from_base64)
+            .collect::<Result<Vec<_>>>()?;
+
+        if signing_certificates.is_empty() {
+            bail!("No signing certificates found in the federation metadata");
+        }
+
+        let login_endpoint = idp
+            .single_sign_on_service
+            .iter()
+            .find(|service| service.binding == HTTP_REDIRECT_BINDING)
+            .or(idp.single_sign_on_service.first())
+            .map(|service| service.location.clone());
+
+        let tenant_id = login_endpoint
+            .as_deref()
+            .and_then(first_path_segment)
+            .or_else(|| first_path_segment(&entity.entity_id));
+
+        Ok(Self {
+            entity_id: entity.entity_id,
+            tenant_id,
+            login_endpoint,
+            signing_certificates,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let xml = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))?;
+        Self::from_xml(xml.trim_start_matches('\u{feff}'))
+    }
+
+    /// Host of the login endpoint, e.g. `login.microsoftonline.us` for Azure Government
+    pub fn authority_host(&self) -> Option<String> {
+        let url = Url::parse(self.login_endpoint.as_ref()?).ok()?;
+        let host = url.host_str()?;
+
+        match url.port() {
+            Some(port) => Some(format!("{}://{}:{}", url.scheme(), host, port)),
+            None if url.scheme() != "https" => Some(format!("{}://{}", url.scheme(), host)),
+            None => Some(host.to_string()),
+        }
+    }
+}
+
+fn first_path_segment(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()?
+        .path_segments()?
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.to_string())
+}
+
+/// Stores the certificates as PEM for signature verification, returning the path written
+pub fn store_certificates(
+    profile_name: &str,
+    certificates: &[SigningCertificate],
+) -> Result<PathBuf> {
+    let directory = match UserDirs::new() {
+        Some(user_dirs) => user_dirs.home_dir().join(".aws/azure_login_certificates"),
+        None => bail!("Unable to get user directories"),
+    };
+    std::fs::create_dir_all(&directory)?;
+
+    let file_name = profile_name
+        .trim_start_matches("profile ")
+        .replace(['/', '\\'], "_");
+    let path = directory.join(format!("{}.pem", file_name));
+
+    let pem: String = certificates.iter().map(|c| c.to_pem()).collect();
+    std::fs::write(&path, pem)?;
+
+    Ok(path)
+}
+
+#[derive(Deserialize, Debug)]
+struct EntityDescriptorXml {
+    #[serde(rename = "entityID")]
+    entity_id: String,
+    #[serde(rename = "IDPSSODescriptor")]
+    idp_sso_descriptor: Option<IdpSsoDescriptorXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdpSsoDescriptorXml {
+    #[serde(rename = "KeyDescriptor", default)]
+    key_descriptor: Vec<KeyDescriptorXml>,
+    #[serde(rename = "SingleSignOnService", default)]
+    single_sign_on_service: Vec<EndpointXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct KeyDescriptorXml {
+    #[serde(rename = "use")]
+    key_use: Option<String>,
+    #[serde(rename = "KeyInfo")]
+    key_info: KeyInfoXml,
+}
+
+#[derive(Deserialize, Debug)]
+struct KeyInfoXml {
+    #[serde(rename = "X509Data")]
+    x509_data: X509DataXml,
+}
+
+#[derive(Deserialize, Debug)]
+struct X509DataXml {
+    #[serde(rename = "X509Certificate")]
+    x509_certificate: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EndpointXml {
+    #[serde(rename = "Binding")]
+    binding: String,
+    #[serde(rename = "Location")]
+    location: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = include_str!("../tests/fixtures/federation_metadata.xml");
+    const TENANT_ID: &str = "11111111-2222-3333-4444-555555555555";
+
+    fn certificate(pem: &str) -> SigningCertificate {
+        SigningCertificate::parse_all(pem).unwrap().remove(0)
+    }
+
+    fn metadata(login_endpoint: Option<&str>) -> FederationMetadata {
+        FederationMetadata {
+            entity_id: format!("https://sts.windows.net/{}/", TENANT_ID),
+            tenant_id: Some(TENANT_ID.to_string()),
+            login_endpoint: login_endpoint.map(str::to_string),
+            signing_certificates: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_federation_metadata() {
+        let metadata = FederationMetadata::from_xml(METADATA).unwrap();
+
+        assert_eq!(
+            metadata.entity_id,
+            format!("https://sts.windows.net/{}/", TENANT_ID)
+        );
+        assert_eq!(metadata.tenant_id.as_deref(), Some(TENANT_ID));
+        // The redirect binding is preferred over the first listed service
+        assert_eq!(
+            metadata.login_endpoint,
+            Some(format!(
+                "https://login.microsoftonline.us/{}/saml2",
+                TENANT_ID
+            ))
+        );
+        assert_eq!(
+            metadata.authority_host().as_deref(),
+            Some("login.microsoftonline.us")
+        );
+
+        // Encryption keys are skipped, keys without a use are signing keys
+        let thumbprints: Vec<String> = metadata
+            .signing_certificates
+            .iter()
+            .map(SigningCertificate::thumbprint)
+            .collect();
+        assert_eq!(
+            thumbprints,
+            [
+                certificate(include_str!("../tests/fixtures/signing_certificate.pem")).thumbprint(),
+                certificate(include_str!("../tests/fixtures/rotated_certificate.pem")).thumbprint(),
+            ]
+        );
+    }
+
+    #[test]
+    fn tenant_falls_back_to_the_entity_id() {
+        let xml: String = METADATA
+            .lines()
+            .filter(|line| !line.contains("<SingleSignOnService"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let metadata = FederationMetadata::from_xml(&xml).unwrap();
+
+        assert_eq!(metadata.login_endpoint, None);
+        assert_eq!(metadata.tenant_id.as_deref(), Some(TENANT_ID));
+        assert_eq!(metadata.authority_host(), None);
+    }
+
+    #[test]
+    fn rejects_metadata_without_an_idp() {
+        let xml = r#"<EntityDescriptor entityID="https://signin.aws.amazon.com/saml" xmlns="urn:oasis:names:tc:SAML:2.0:metadata"><SPSSODescriptor /></EntityDescriptor>"#;
+
+        assert_eq!(
+            FederationMetadata::from_xml(xml).unwrap_err().to_string(),
+            "No IDPSSODescriptor found in the federation metadata"
+        );
+    }
+
+    #[test]
+    fn authority_host_keeps_ports_and_plain_http() {
+        assert_eq!(
+            metadata(Some("https://login.microsoftonline.com/tenant/saml2"))
+                .authority_host()
+                .as_deref(),
+            Some("login.microsoftonline.com")
+        );
+        assert_eq!(
+            metadata(Some("http://localhost:8080/tenant/saml2"))
+                .authority_host()
+                .as_deref(),
+            Some("http://localhost:8080")
+        );
+        assert_eq!(
+            metadata(Some("https://login.example:8443/tenant/saml2"))
+                .authority_host()
+                .as_deref(),
+            Some("https://login.example:8443")
+        );
+        assert_eq!(metadata(Some("not a url")).authority_host(), None);
+    }
+}
@@ -2,7 +2,24 @@ use anyhow::Result;
 use base64::engine::general_purpose;
 use base64::Engine;
 
+/// Decodes base64 wrapped over multiple lines, as found in XML and PEM
+pub fn base64_decode(string: &str) -> Result<Vec<u8>> {
+    let cleaned: String = string.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(general_purpose::STANDARD.decode(cleaned)?)
+}
+
 pub fn base64_decode_to_string(string: &str) -> Result<String> {
     let output_vec = general_purpose::STANDARD.decode(string)?;
     Ok(String::from_utf8(output_vec)?)
 }
+
+pub fn format_duration(seconds: i64) -> String {
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+
+    match (hours, minutes) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, 0) => format!("{}h", hours),
+        (hours, minutes) => format!("{}h {}m", hours, minutes),
+    }
+}
@@ -0,0 +1,327 @@
+use crate::aad_error::AzureAdError;
+use crate::helpers::{base64_decode_to_string, format_duration};
+use crate::saml_request::{decode_saml_request, LoginUrl};
+use crate::saml_response::decryption::DecryptionKey;
+use crate::saml_response::{
+    AssertionKind, ResponseEnvelope, Role, SamlAssertion, SamlResponse, ROLE_ATTRIBUTE,
+};
+use crate::sso::read_saml_response;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use file_manager::clock_skew::ClockSkew;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use url::Url;
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use xml::writer::EmitterConfig;
+
+/// A decoded SAMLRequest or SAMLResponse, for debugging federation setups
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inspection {
+    Request {
+        login_url: Box<LoginUrl>,
+        xml: String,
+    },
+    Response {
+        response: Box<ResponseEnvelope>,
+        assertion: Option<Box<SamlAssertion>>,
+        /// Why the assertion isn't shown, when it is absent or can't be read
+        assertion_note: Option<String>,
+        roles: Vec<Role>,
+        xml: String,
+    },
+}
+
+impl Inspection {
+    /// Inspects a login URL carrying a SAMLRequest, or a SAMLResponse from a file or `-` for stdin
+    pub fn from_input(input: &str, decryption_key: Option<&DecryptionKey>) -> Result<Self> {
+        if input.starts_with("http://") || input.starts_with("https://") {
+            Self::from_login_url(input)
+        } else {
+            Self::from_saml_response(&read_saml_response(input)?, decryption_key)
+        }
+    }
+
+    pub fn from_login_url(url: &str) -> Result<Self> {
+        let login_url = LoginUrl::parse(url)?;
+        let encoded = Url::parse(url)?
+            .query_pairs()
+            .find(|(key, _)| key == "SAMLRequest")
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_default();
+
+        Ok(Self::Request {
+            login_url: Box::new(login_url),
+            xml: pretty_print_xml(&decode_saml_request(&encoded)?)?,
+        })
+    }
+
+    pub fn from_saml_response(
+        saml_response: &str,
+        decryption_key: Option<&DecryptionKey>,
+    ) -> Result<Self> {
+        let xml = base64_decode_to_string(saml_response)?;
+        let response = ResponseEnvelope::from_xml(&xml)?;
+
+        // The rest of the response is still worth showing when the assertion can't be
+        let (assertion, assertion_note) = match (response.assertion, decryption_key) {
+            (AssertionKind::Absent, _) => (None, Some("Absent".to_string())),
+            (AssertionKind::Encrypted, None) => (
+                None,
+                Some("Encrypted, set azure_saml_decryption_key to read it".to_string()),
+            ),
+            _ => match SamlResponse::from_xml_with_key(&xml, decryption_key) {
+                Ok(saml_response) => (Some(Box::new(saml_response.assertion)), None),
+                Err(error) => (None, Some(format!("Unreadable: {}", error))),
+            },
+        };
+
+        // Malformed values are left out here, they still show in the attributes
+        let roles = assertion
+            .as_ref()
+            .and_then(|assertion| assertion.attribute(ROLE_ATTRIBUTE))
+            .map(|attr| {
+                attr.values
+                    .iter()
+                    .filter_map(|value| Role::parse(value).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self::Response {
+            response: Box::new(response),
+            assertion,
+            assertion_note,
+            roles,
+            xml: pretty_print_xml(&xml)?,
+        })
+    }
+}
+
+impl Display for Inspection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Inspection::Request { login_url, xml } => {
+                let request = &login_url.authn_request;
+
+                writeln!(f, "SAML Request")?;
+                field(f, "Endpoint", Some(&login_url.endpoint))?;
+                field(f, "ID", Some(&request.id))?;
+                field(f, "Issue Instant", Some(&request.issue_instant))?;
+                field(f, "Issuer", Some(&request.issuer))?;
+                field(f, "ACS URL", Some(&request.assertion_consumer_service_url))?;
+                field(f, "Force Authn", Some(&request.force_authn))?;
+                field(f, "Is Passive", Some(&request.is_passive))?;
+                field(f, "NameID Format", request.name_id_policy_format.as_ref())?;
+                if let Some(context) = &request.requested_authn_context {
+                    field(
+                        f,
+                        "Authn Context",
+                        Some(&format!(
+                            "{} ({})",
+                            context.class_refs.join(", "),
+                            context.comparison
+                        )),
+                    )?;
+                }
+                field(f, "Relay State", login_url.relay_state.as_ref())?;
+                field(f, "Login Hint", login_url.login_hint.as_ref())?;
+                field(f, "Domain Hint", login_url.domain_hint.as_ref())?;
+                field(f, "Prompt", login_url.prompt.as_ref())?;
+
+                write!(f, "\n{}", xml)
+            }
+            Inspection::Response {
+                response,
+                assertion,
+                assertion_note,
+                roles,
+                xml,
+            } => {
+                writeln!(f, "SAML Response")?;
+                field(f, "ID", response.id.as_ref())?;
+                field(f, "Issuer", response.issuer.as_ref())?;
+                field(f, "Destination", response.destination.as_ref())?;
+                field(f, "In Response To", response.in_response_to.as_ref())?;
+                field(f, "Issue Instant", response.issue_instant.as_ref())?;
+                let status = response.status_code.as_deref().map(|code| {
+                    match response.sub_status_code.as_deref() {
+                        Some(sub_code) => {
+                            format!("{} ({})", short_status(code), short_status(sub_code))
+                        }
+                        None => short_status(code).to_string(),
+                    }
+                });
+                field(f, "Status", status.as_ref())?;
+                field(f, "Status Message", response.status_message.as_ref())?;
+                field(
+                    f,
+                    "Hint",
+                    response
+                        .status_message
+                        .as_deref()
+                        .and_then(AzureAdError::from_page_text)
+                        .and_then(|error| error.hint()),
+                )?;
+
+                writeln!(f, "\nAssertion")?;
+                let assertion = match assertion {
+                    Some(assertion) => assertion,
+                    None => {
+                        writeln!(f, "  {}", assertion_note.as_deref().unwrap_or("Absent"))?;
+                        return write!(f, "\n{}", xml);
+                    }
+                };
+                field(f, "ID", assertion.id.as_ref())?;
+                field(f, "Issuer", Some(&assertion.issuer))?;
+                if let Some(subject) = &assertion.subject {
+                    field(f, "Subject", Some(&subject.name_id))?;
+                    field(f, "Recipient", subject.recipient.as_ref())?;
+                }
+                if let Some(conditions) = &assertion.conditions {
+                    field(f, "Audience", Some(&conditions.audiences.join(", ")))?;
+                    field(f, "Not Before", conditions.not_before.as_ref())?;
+                    field(
+                        f,
+                        "Not On Or After",
+                        conditions
+                            .not_on_or_after
+                            .map(|instant| format!("{} ({})", instant, validity(instant)))
+                            .as_ref(),
+                    )?;
+                }
+                if let Some(authn_statement) = &assertion.authn_statement {
+                    field(f, "Authn Instant", authn_statement.authn_instant.as_ref())?;
+                    field(
+                        f,
+                        "Session Until",
+                        authn_statement.session_not_on_or_after.as_ref(),
+                    )?;
+                    field(
+                        f,
+                        "Authn Context",
+                        authn_statement.authn_context_class_ref.as_ref(),
+                    )?;
+                }
+
+                writeln!(f, "\nAttributes")?;
+                for attribute in &assertion.attributes {
+                    let name = match &attribute.friendly_name {
+                        Some(friendly_name) => format!("{} ({})", attribute.name, friendly_name),
+                        None => attribute.name.clone(),
+                    };
+                    writeln!(f, "  {}", name)?;
+                    for value in &attribute.values {
+                        writeln!(f, "    {}", value)?;
+                    }
+                }
+
+                writeln!(f, "\nRoles")?;
+                if roles.is_empty() {
+                    writeln!(f, "  None")?;
+                }
+                for role in roles {
+                    writeln!(f, "  {}", role)?;
+                }
+
+                write!(f, "\n{}", xml)
+            }
+        }
+    }
+}
+
+fn field<T: Display>(f: &mut Formatter<'_>, label: &str, value: Option<T>) -> std::fmt::Result {
+    match value {
+        Some(value) => writeln!(f, "  {:<18}{}", format!("{}:", label), value),
+        None => Ok(()),
+    }
+}
+
+/// The last part of a status URN, e.g. `Success`
+fn short_status(code: &str) -> &str {
+    code.rsplit(':').next().unwrap_or(code)
+}
+
+fn validity(not_on_or_after: DateTime<Utc>) -> String {
+    let remaining = (not_on_or_after - ClockSkew::now()).num_seconds();
+    if remaining > 0 {
+        format!("valid for {}", format_duration(remaining))
+    } else {
+        format!("expired {} ago", format_duration(-remaining))
+    }
+}
+
+fn pretty_print_xml(xml: &str) -> Result<String> {
+    let config = ParserConfig::new()
+        .trim_whitespace(true)
+        .ignore_comments(false);
+    let reader = EventReader::new_with_config(xml.as_bytes(), config);
+
+    let mut output = Vec::new();
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .write_document_declaration(false)
+        .create_writer(&mut output);
+
+    for event in reader {
+        let event = event?;
+        if matches!(event, XmlEvent::StartDocument { .. }) {
+            continue;
+        }
+        if let Some(event) = event.as_writer_event() {
+            writer.write(event)?;
+        }
+    }
+
+    Ok(String::from_utf8(output)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose;
+    use base64::Engine;
+
+    const ERROR_RESPONSE: &str = r#"<samlp:Response ID="_error" Version="2.0" IssueInstant="2023-09-14T03:12:45.123Z" Destination="https://signin.aws.amazon.com/saml" InResponseTo="id123" xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Issuer xmlns="urn:oasis:names:tc:SAML:2.0:assertion">https://sts.windows.net/tenant/</Issuer><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Requester"><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:RequestDenied"/></samlp:StatusCode><samlp:StatusMessage>AADSTS50105: Your administrator has configured the application AWS to block users unless they are specifically granted access.</samlp:StatusMessage></samlp:Status></samlp:Response>"#;
+
+    const ENCRYPTED_RESPONSE: &str = r#"<samlp:Response ID="_encrypted" xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Issuer xmlns="urn:oasis:names:tc:SAML:2.0:assertion">https://sts.windows.net/tenant/</Issuer><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status><EncryptedAssertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><xenc:EncryptedData xmlns:xenc="http://www.w3.org/2001/04/xmlenc#"><xenc:EncryptionMethod Algorithm="http://www.w3.org/2009/xmlenc11#aes256-gcm"/><xenc:CipherData><xenc:CipherValue>AAAA</xenc:CipherValue></xenc:CipherData></xenc:EncryptedData></EncryptedAssertion></samlp:Response>"#;
+
+    fn inspect(xml: &str) -> Inspection {
+        Inspection::from_saml_response(&general_purpose::STANDARD.encode(xml), None).unwrap()
+    }
+
+    #[test]
+    fn inspects_error_response() {
+        let inspection = inspect(ERROR_RESPONSE);
+
+        let Inspection::Response {
+            response,
+            assertion,
+            assertion_note,
+            ..
+        } = &inspection
+        else {
+            panic!("Expected a response");
+        };
+        assert!(assertion.is_none());
+        assert_eq!(assertion_note.as_deref(), Some("Absent"));
+        assert_eq!(
+            response.issuer.as_deref(),
+            Some("https://sts.windows.net/tenant/")
+        );
+
+        let text = inspection.to_string();
+        assert!(text.contains("Requester (RequestDenied)"));
+        assert!(text.contains("AADSTS50105: Your administrator"));
+        assert!(text.contains("Hint:"));
+    }
+
+    #[test]
+    fn inspects_encrypted_response_without_key() {
+        let text = inspect(ENCRYPTED_RESPONSE).to_string();
+
+        assert!(text.contains("Success"));
+        assert!(text.contains("Encrypted, set azure_saml_decryption_key to read it"));
+    }
+}
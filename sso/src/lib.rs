@@ -1,4 +1,15 @@
+pub mod aad_error;
+pub mod clock;
+pub mod cloud;
+pub mod federation_metadata;
 pub mod helpers;
+pub mod inspect;
+pub mod partition;
+pub mod role_chain;
+pub mod role_filter;
 pub mod saml_request;
 pub mod saml_response;
+pub mod session;
+pub mod session_name;
 pub mod sso;
+pub mod sts_retry;
@@ -0,0 +1,428 @@
+use crate::clock::{CorrectedTimeSource, ServerDateInterceptor};
+use anyhow::{bail, Result};
+use aws_config::retry::RetryConfig;
+use aws_config::SdkConfig;
+use aws_credential_types::Credentials;
+use aws_sdk_sts::config::Region;
+use file_manager::aws_config::AwsConfig;
+use log::warn;
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AwsPartition {
+    Aws,
+    AwsUsGov,
+    AwsCn,
+}
+
+impl AwsPartition {
+    /// Reads the partition from an ARN, e.g. `arn:aws-us-gov:iam::123456789012:role/Admin`
+    pub fn from_arn(arn: &str) -> Option<Self> {
+        match arn.split(':').nth(1)? {
+            "aws" => Some(AwsPartition::Aws),
+            "aws-us-gov" => Some(AwsPartition::AwsUsGov),
+            "aws-cn" => Some(AwsPartition::AwsCn),
+            _ => None,
+        }
+    }
+
+    pub fn from_region(region: Option<&str>) -> Self {
+        match region {
+            Some(r) if r.starts_with("us-gov") => AwsPartition::AwsUsGov,
+            Some(r) if r.starts_with("cn-") => AwsPartition::AwsCn,
+            _ => AwsPartition::Aws,
+        }
+    }
+
+    /// The partition of `azure_default_role_arn` when it is an ARN, otherwise of the region
+    pub fn from_config(config: &AwsConfig) -> Self {
+        config
+            .azure_default_role_arn
+            .as_deref()
+            .and_then(Self::from_arn)
+            .unwrap_or_else(|| Self::from_region(config.region.as_deref()))
+    }
+
+    pub fn signin_host(&self) -> &'static str {
+        match self {
+            AwsPartition::Aws => "signin.aws.amazon.com",
+            AwsPartition::AwsUsGov => "signin.amazonaws-us-gov.com",
+            AwsPartition::AwsCn => "signin.amazonaws.cn",
+        }
+    }
+
+    /// Region used when the profile's region is missing or in another partition
+    pub fn default_region(&self) -> &'static str {
+        match self {
+            AwsPartition::Aws => "us-east-1",
+            AwsPartition::AwsUsGov => "us-gov-west-1",
+            AwsPartition::AwsCn => "cn-north-1",
+        }
+    }
+
+    /// The AWS sign in URL assertions are posted to, regional sign in is only available in the `aws` partition
+    pub fn signin_url(&self, region: Option<&str>, regional: bool) -> String {
+        match region {
+            Some(region)
+                if regional
+                    && *self == AwsPartition::Aws
+                    && Self::from_region(Some(region)) == *self =>
+            {
+                format!("https://{}.{}/saml", region, self.signin_host())
+            }
+            _ => format!("https://{}/saml", self.signin_host()),
+        }
+    }
+}
+
+impl Display for AwsPartition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AwsPartition::Aws => write!(f, "aws"),
+            AwsPartition::AwsUsGov => write!(f, "aws-us-gov"),
+            AwsPartition::AwsCn => write!(f, "aws-cn"),
+        }
+    }
+}
+
+/// Where STS calls for a role go, from the role's partition and the profile's endpoint settings
+#[derive(Clone, Debug)]
+pub struct StsEndpoint {
+    pub partition: AwsPartition,
+    pub region: Option<String>,
+    pub use_fips: Option<bool>,
+    pub use_dual_stack: Option<bool>,
+    pub regional: bool,
+    pub endpoint_url: Option<String>,
+}
+
+impl StsEndpoint {
+    pub fn from_config(config: &AwsConfig, role_arn: &str) -> Result<Self> {
+        let partition = AwsPartition::from_arn(role_arn)
+            .unwrap_or_else(|| AwsPartition::from_region(config.region.as_deref()));
+
+        let sts_regional_endpoints = config
+            .sts_regional_endpoints
+            .clone()
+            .or_else(|| std::env::var("AWS_STS_REGIONAL_ENDPOINTS").ok());
+        let regional = match sts_regional_endpoints.as_deref().map(str::trim) {
+            None | Some("regional") => true,
+            Some("legacy") => false,
+            Some(other) => bail!(
+                "Unknown sts_regional_endpoints '{}', expected regional or legacy",
+                other
+            ),
+        };
+
+        Ok(Self {
+            partition,
+            region: config.region.clone(),
+            use_fips: config.use_fips_endpoint,
+            use_dual_stack: config.use_dualstack_endpoint,
+            regional,
+            endpoint_url: Self::configured_endpoint_url(config, |name| std::env::var(name).ok()),
+        })
+    }
+
+    /// Like the SDKs, `AWS_ENDPOINT_URL_STS` then `AWS_ENDPOINT_URL` win over the profile's `azure_sts_endpoint_url`
+    fn configured_endpoint_url(
+        config: &AwsConfig,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Option<String> {
+        let ignore_configured = env("AWS_IGNORE_CONFIGURED_ENDPOINT_URLS")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"));
+        if ignore_configured {
+            return None;
+        }
+
+        env("AWS_ENDPOINT_URL_STS")
+            .or_else(|| env("AWS_ENDPOINT_URL"))
+            .or_else(|| config.azure_sts_endpoint_url.clone())
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+    }
+
+    /// The profile's region (or `AWS_REGION`) when it is in the role's partition, else the partition's default
+    pub fn signing_region(&self) -> String {
+        self.signing_region_with_env(|name| std::env::var(name).ok())
+    }
+
+    fn signing_region_with_env(&self, env: impl Fn(&str) -> Option<String>) -> String {
+        let region = self
+            .region
+            .clone()
+            .or_else(|| env("AWS_REGION"))
+            .or_else(|| env("AWS_DEFAULT_REGION"));
+
+        match region {
+            Some(region) if AwsPartition::from_region(Some(&region)) == self.partition => region,
+            Some(region) => {
+                warn!(
+                    "Region {} is not in the {} partition, using {} for STS",
+                    region,
+                    self.partition,
+                    self.partition.default_region()
+                );
+                self.partition.default_region().to_string()
+            }
+            None => self.partition.default_region().to_string(),
+        }
+    }
+
+    /// The legacy global endpoint only exists in the `aws` partition, without FIPS or dual-stack
+    fn uses_global_endpoint(&self) -> bool {
+        !self.regional
+            && self.partition == AwsPartition::Aws
+            && self.use_fips != Some(true)
+            && self.use_dual_stack != Some(true)
+    }
+
+    pub async fn client(&self, credentials: Option<Credentials>) -> aws_sdk_sts::Client {
+        // Failed calls are retried by StsRetry, which knows how long the assertion stays valid
+        let loader = aws_config::from_env()
+            .retry_config(RetryConfig::disabled())
+            .time_source(CorrectedTimeSource);
+        let mut loader = match credentials {
+            Some(credentials) => loader.credentials_provider(credentials),
+            None => loader.no_credentials(),
+        };
+
+        // A custom endpoint is used as is, the SDK refuses to combine it with FIPS or dual-stack
+        if let Some(endpoint_url) = &self.endpoint_url {
+            loader = loader
+                .endpoint_url(endpoint_url)
+                .use_fips(false)
+                .use_dual_stack(false)
+                .region(Region::new(self.signing_region()));
+
+            return sts_client(&loader.load().await);
+        }
+
+        if let Some(use_fips) = self.use_fips {
+            loader = loader.use_fips(use_fips);
+        }
+        if let Some(use_dual_stack) = self.use_dual_stack {
+            loader = loader.use_dual_stack(use_dual_stack);
+        }
+
+        loader = if self.uses_global_endpoint() {
+            loader
+                .endpoint_url("https://sts.amazonaws.com")
+                .region(Region::new(self.partition.default_region()))
+        } else {
+            loader.region(Region::new(self.signing_region()))
+        };
+
+        sts_client(&loader.load().await)
+    }
+}
+
+fn sts_client(sdk_config: &SdkConfig) -> aws_sdk_sts::Client {
+    let config = aws_sdk_sts::config::Builder::from(sdk_config)
+        .interceptor(ServerDateInterceptor)
+        .build();
+    aws_sdk_sts::Client::from_conf(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn endpoint_url(config_url: Option<&str>, env: &[(&str, &str)]) -> Option<String> {
+        let config = AwsConfig {
+            azure_sts_endpoint_url: config_url.map(str::to_string),
+            ..AwsConfig::default()
+        };
+        let env: HashMap<&str, &str> = env.iter().copied().collect();
+
+        StsEndpoint::configured_endpoint_url(&config, |name| {
+            env.get(name).map(|value| value.to_string())
+        })
+    }
+
+    #[test]
+    fn env_endpoint_urls_win_over_the_profile() {
+        let profile = Some("http://profile:4566");
+
+        assert_eq!(
+            endpoint_url(profile, &[]).as_deref(),
+            Some("http://profile:4566")
+        );
+        assert_eq!(
+            endpoint_url(profile, &[("AWS_ENDPOINT_URL", "http://all:4566")]).as_deref(),
+            Some("http://all:4566")
+        );
+        assert_eq!(
+            endpoint_url(
+                profile,
+                &[
+                    ("AWS_ENDPOINT_URL", "http://all:4566"),
+                    ("AWS_ENDPOINT_URL_STS", " http://sts:4566 "),
+                ]
+            )
+            .as_deref(),
+            Some("http://sts:4566")
+        );
+        assert_eq!(endpoint_url(None, &[]), None);
+    }
+
+    #[test]
+    fn ignoring_configured_endpoint_urls_ignores_all_of_them() {
+        assert_eq!(
+            endpoint_url(
+                Some("http://profile:4566"),
+                &[
+                    ("AWS_ENDPOINT_URL_STS", "http://sts:4566"),
+                    ("AWS_IGNORE_CONFIGURED_ENDPOINT_URLS", "TRUE"),
+                ]
+            ),
+            None
+        );
+    }
+
+    fn endpoint(partition: AwsPartition, region: Option<&str>) -> StsEndpoint {
+        StsEndpoint {
+            partition,
+            region: region.map(str::to_string),
+            use_fips: None,
+            use_dual_stack: None,
+            regional: true,
+            endpoint_url: None,
+        }
+    }
+
+    #[test]
+    fn partition_from_arn() {
+        assert_eq!(
+            AwsPartition::from_arn("arn:aws:iam::123456789012:role/Admin"),
+            Some(AwsPartition::Aws)
+        );
+        assert_eq!(
+            AwsPartition::from_arn("arn:aws-us-gov:iam::123456789012:role/Admin"),
+            Some(AwsPartition::AwsUsGov)
+        );
+        assert_eq!(
+            AwsPartition::from_arn("arn:aws-cn:iam::123456789012:role/Admin"),
+            Some(AwsPartition::AwsCn)
+        );
+        assert_eq!(
+            AwsPartition::from_arn("arn:aws-iso:iam::123456789012:role/Admin"),
+            None
+        );
+        assert_eq!(AwsPartition::from_arn("Admin"), None);
+    }
+
+    #[test]
+    fn partition_from_region() {
+        assert_eq!(
+            AwsPartition::from_region(Some("us-gov-west-1")),
+            AwsPartition::AwsUsGov
+        );
+        assert_eq!(
+            AwsPartition::from_region(Some("cn-northwest-1")),
+            AwsPartition::AwsCn
+        );
+        assert_eq!(
+            AwsPartition::from_region(Some("ap-southeast-2")),
+            AwsPartition::Aws
+        );
+        assert_eq!(AwsPartition::from_region(None), AwsPartition::Aws);
+    }
+
+    #[test]
+    fn partition_from_config_prefers_the_role_arn() {
+        let config = AwsConfig {
+            azure_default_role_arn: Some("arn:aws-us-gov:iam::123456789012:role/Admin".to_string()),
+            region: Some("us-east-1".to_string()),
+            ..AwsConfig::default()
+        };
+        assert_eq!(AwsPartition::from_config(&config), AwsPartition::AwsUsGov);
+
+        let config = AwsConfig {
+            azure_default_role_arn: Some("Admin".to_string()),
+            region: Some("cn-north-1".to_string()),
+            ..AwsConfig::default()
+        };
+        assert_eq!(AwsPartition::from_config(&config), AwsPartition::AwsCn);
+    }
+
+    #[test]
+    fn regional_signin_is_only_for_the_aws_partition() {
+        assert_eq!(
+            AwsPartition::Aws.signin_url(Some("eu-west-1"), true),
+            "https://eu-west-1.signin.aws.amazon.com/saml"
+        );
+        assert_eq!(
+            AwsPartition::Aws.signin_url(Some("eu-west-1"), false),
+            "https://signin.aws.amazon.com/saml"
+        );
+        assert_eq!(
+            AwsPartition::Aws.signin_url(Some("us-gov-west-1"), true),
+            "https://signin.aws.amazon.com/saml"
+        );
+        assert_eq!(
+            AwsPartition::AwsUsGov.signin_url(Some("us-gov-west-1"), true),
+            "https://signin.amazonaws-us-gov.com/saml"
+        );
+        assert_eq!(
+            AwsPartition::AwsCn.signin_url(Some("cn-north-1"), true),
+            "https://signin.amazonaws.cn/saml"
+        );
+        assert_eq!(
+            AwsPartition::Aws.signin_url(None, true),
+            "https://signin.aws.amazon.com/saml"
+        );
+    }
+
+    #[test]
+    fn signing_region_falls_back_to_the_partition_default() {
+        let env = |name: &str| (name == "AWS_REGION").then(|| "eu-west-2".to_string());
+
+        assert_eq!(
+            endpoint(AwsPartition::Aws, Some("ap-southeast-2")).signing_region_with_env(env),
+            "ap-southeast-2"
+        );
+        assert_eq!(
+            endpoint(AwsPartition::Aws, None).signing_region_with_env(env),
+            "eu-west-2"
+        );
+        assert_eq!(
+            endpoint(AwsPartition::AwsUsGov, Some("us-east-1")).signing_region_with_env(env),
+            "us-gov-west-1"
+        );
+        assert_eq!(
+            endpoint(AwsPartition::AwsCn, None).signing_region_with_env(env),
+            "cn-north-1"
+        );
+        assert_eq!(
+            endpoint(AwsPartition::Aws, None).signing_region_with_env(|_| None),
+            "us-east-1"
+        );
+    }
+
+    #[test]
+    fn global_endpoint_only_for_legacy_aws_without_fips_or_dual_stack() {
+        let legacy = StsEndpoint {
+            regional: false,
+            ..endpoint(AwsPartition::Aws, Some("eu-west-1"))
+        };
+        assert!(legacy.uses_global_endpoint());
+        assert!(!endpoint(AwsPartition::Aws, Some("eu-west-1")).uses_global_endpoint());
+        assert!(!StsEndpoint {
+            partition: AwsPartition::AwsUsGov,
+            ..legacy.clone()
+        }
+        .uses_global_endpoint());
+        assert!(!StsEndpoint {
+            use_fips: Some(true),
+            ..legacy.clone()
+        }
+        .uses_global_endpoint());
+        assert!(!StsEndpoint {
+            use_dual_stack: Some(true),
+            ..legacy
+        }
+        .uses_global_endpoint());
+    }
+}
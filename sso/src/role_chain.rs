@@ -0,0 +1,170 @@
+use crate::partition::StsEndpoint;
+use crate::session::{SessionOptions, SessionSummary};
+use crate::session_name::render_session_name;
+use crate::sso::credential_from_sts;
+use crate::sts_retry::StsRetry;
+use anyhow::{anyhow, bail, Result};
+use aws_credential_types::Credentials;
+use file_manager::aws_config::AwsConfig;
+use file_manager::aws_credential::AwsCredential;
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+const DEFAULT_SESSION_NAME: &str = "aws-azure-login";
+
+/// A role assumed with `sts:AssumeRole` from the credentials of the previous hop
+#[derive(Clone, Debug)]
+pub struct ChainedRole {
+    pub role_arn: String,
+    pub external_id: Option<String>,
+    pub session_name: Option<String>,
+    pub session_name_template: Option<String>,
+}
+
+impl ChainedRole {
+    /// The `azure_chain_*` hop run after the SAML login of the same profile
+    pub fn from_azure_chain(config: &AwsConfig) -> Option<Self> {
+        Some(Self {
+            role_arn: config.azure_chain_role_arn.clone()?,
+            external_id: config.azure_chain_external_id.clone(),
+            session_name: config.azure_chain_session_name.clone(),
+            session_name_template: config.azure_session_name_template.clone(),
+        })
+    }
+
+    /// The standard `role_arn` hop from `source_profile`, for profiles without an Azure login of their own
+    pub fn from_source_profile(config: &AwsConfig) -> Option<(Self, String)> {
+        if config.azure_tenant_id.is_some() {
+            return None;
+        }
+
+        let chained_role = Self {
+            role_arn: config.role_arn.clone()?,
+            external_id: config.external_id.clone(),
+            session_name: config.role_session_name.clone(),
+            session_name_template: config.azure_session_name_template.clone(),
+        };
+        Some((chained_role, config.source_profile.clone()?))
+    }
+
+    /// A fixed session name wins over `azure_session_name_template`, which wins over the default
+    pub fn session_name(&self, profile_name: &str) -> String {
+        match (&self.session_name, &self.session_name_template) {
+            (Some(session_name), _) => session_name.clone(),
+            (None, Some(template)) => render_session_name(template, profile_name),
+            (None, None) => DEFAULT_SESSION_NAME.to_string(),
+        }
+    }
+}
+
+/// A profile reached from its `source_profile` by assuming its `role_arn`
+#[derive(Clone, Debug)]
+pub struct ChainHop {
+    pub profile_name: String,
+    pub chained_role: ChainedRole,
+    pub session: SessionOptions,
+    pub sts: StsEndpoint,
+}
+
+/// Follows `source_profile` to the profile with the Azure login, returning it and the hops to take from it in order
+pub fn resolve_source_chain(
+    profile_name: &str,
+    configs: &HashMap<String, AwsConfig>,
+) -> Result<(String, Vec<ChainHop>)> {
+    let mut hops = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = profile_name.to_string();
+
+    let source_region = loop {
+        let config = AwsConfig::get(&current, configs)?;
+        let (chained_role, source_profile) = match ChainedRole::from_source_profile(&config) {
+            Some(hop) => hop,
+            None => break config.region,
+        };
+
+        if !visited.insert(AwsConfig::sanitize_profile_name(&current)) {
+            bail!("The source_profile chain of {} loops", profile_name);
+        }
+
+        hops.push(ChainHop {
+            profile_name: current,
+            sts: StsEndpoint::from_config(&config, &chained_role.role_arn)?,
+            session: SessionOptions::from_config(&config)?,
+            chained_role,
+        });
+        current = source_profile;
+    };
+
+    // Hops without a region of their own sign with the region of the profile they come from
+    hops.reverse();
+    let mut region = source_region;
+    for hop in hops.iter_mut() {
+        if hop.sts.region.is_none() {
+            hop.sts.region = region.clone();
+        }
+        region = hop.sts.region.clone();
+    }
+
+    Ok((current, hops))
+}
+
+/// Assumes the role from the source credentials, returning the credentials and what was applied to the session
+pub async fn assume_chained_role(
+    profile_name: &str,
+    source: &AwsCredential,
+    source_session: Option<&SessionSummary>,
+    chained_role: &ChainedRole,
+    session: &SessionOptions,
+    sts: &StsEndpoint,
+) -> Result<(AwsCredential, SessionSummary)> {
+    let credentials = Credentials::new(
+        source
+            .aws_access_key_id
+            .clone()
+            .ok_or(anyhow!("No access key ID in the source credentials"))?,
+        source
+            .aws_secret_access_key
+            .clone()
+            .ok_or(anyhow!("No secret access key in the source credentials"))?,
+        source.aws_session_token.clone(),
+        source.aws_expiration.map(SystemTime::from),
+        "aws-azure-login",
+    );
+
+    let sts_client = sts.client(Some(credentials)).await;
+
+    let session_name = chained_role.session_name(profile_name);
+    let mut retry = StsRetry::new(
+        format!("assume {}", chained_role.role_arn),
+        source.aws_expiration,
+        "source credentials",
+    );
+    let response = loop {
+        let request = sts_client
+            .assume_role()
+            .role_arn(&chained_role.role_arn)
+            .role_session_name(&session_name)
+            .set_external_id(chained_role.external_id.clone())
+            .set_policy(session.policy.clone())
+            .set_policy_arns(session.sts_policy_arns())
+            .set_tags(session.sts_tags())
+            .set_transitive_tag_keys(session.sts_transitive_tag_keys())
+            .set_source_identity(session.source_identity.clone());
+
+        match request.send().await {
+            Ok(response) => break response,
+            Err(error) => retry.backoff(error).await?,
+        }
+    };
+
+    let summary = SessionSummary::chain(
+        source_session,
+        &chained_role.role_arn,
+        response.assumed_role_user().and_then(|user| user.arn()),
+        session,
+        response.source_identity(),
+    );
+    let credential = credential_from_sts(profile_name, response.credentials)?;
+
+    Ok((credential, summary))
+}
@@ -0,0 +1,174 @@
+use crate::saml_response::Role;
+use anyhow::{anyhow, Result};
+use file_manager::aws_config::AwsConfig;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Narrows the roles offered for a profile, from comma separated globs or `regex:` patterns
+#[derive(Clone, Debug)]
+pub struct RoleFilter {
+    patterns: Vec<Regex>,
+}
+
+impl RoleFilter {
+    pub fn parse(patterns: &str) -> Result<Self> {
+        let patterns = patterns
+            .split(',')
+            .map(|pattern| pattern.trim())
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| {
+                let regex = match pattern.strip_prefix("regex:") {
+                    Some(regex) => regex.to_string(),
+                    None => glob_to_regex(pattern),
+                };
+                Regex::new(&regex)
+                    .map_err(|e| anyhow!("Invalid azure_role_filter pattern '{}': {}", pattern, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { patterns })
+    }
+
+    pub fn from_config(config: &AwsConfig) -> Result<Option<Self>> {
+        config
+            .azure_role_filter
+            .as_deref()
+            .map(Self::parse)
+            .transpose()
+    }
+
+    /// Patterns are tried against the role ARN, `<account ID>/<role name>` and `<alias>/<role name>`
+    pub fn matches(&self, role: &Role, aliases: &HashMap<String, String>) -> bool {
+        let mut candidates = vec![
+            role.role_arn.clone(),
+            format!("{}/{}", role.account_id(), role.role_name()),
+        ];
+        if let Some(alias) = aliases.get(role.account_id()) {
+            candidates.push(format!("{}/{}", alias, role.role_name()));
+        }
+
+        self.patterns.iter().any(|pattern| {
+            candidates
+                .iter()
+                .any(|candidate| pattern.is_match(candidate))
+        })
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(role_arn: &str) -> Role {
+        Role {
+            role_arn: role_arn.to_string(),
+            principal_arn: "arn:aws:iam::123456789012:saml-provider/AzureAD".to_string(),
+        }
+    }
+
+    fn matches(patterns: &str, role_arn: &str) -> bool {
+        RoleFilter::parse(patterns)
+            .unwrap()
+            .matches(&role(role_arn), &HashMap::new())
+    }
+
+    #[test]
+    fn glob_escapes_regex_characters() {
+        assert_eq!(glob_to_regex("a.b+c/d"), r"^a\.b\+c/d$");
+        assert_eq!(glob_to_regex("*/Dev?"), "^.*/Dev.$");
+
+        assert!(matches(
+            "123456789012/app.ops+admin",
+            "arn:aws:iam::123456789012:role/app.ops+admin"
+        ));
+        assert!(!matches(
+            "123456789012/app.ops+admin",
+            "arn:aws:iam::123456789012:role/appXopssadmin"
+        ));
+        assert!(!matches(
+            "123456789012/app.ops+admin",
+            "arn:aws:iam::123456789012:role/app.opsssadmin"
+        ));
+    }
+
+    #[test]
+    fn glob_is_anchored() {
+        assert!(matches("*/Admin", "arn:aws:iam::123456789012:role/Admin"));
+        assert!(!matches(
+            "*/Admin",
+            "arn:aws:iam::123456789012:role/AdminReadOnly"
+        ));
+        assert!(matches(
+            "arn:aws:iam::*:role/Admin*",
+            "arn:aws:iam::123456789012:role/AdminReadOnly"
+        ));
+    }
+
+    #[test]
+    fn regex_patterns_are_used_as_is() {
+        assert!(matches(
+            "regex:^1234\\d+/(Admin|Dev)$",
+            "arn:aws:iam::123456789012:role/Dev"
+        ));
+        assert!(!matches(
+            "regex:^1234\\d+/(Admin|Dev)$",
+            "arn:aws:iam::123456789012:role/Developer"
+        ));
+        // Unanchored regexes match anywhere
+        assert!(matches(
+            "regex:Read",
+            "arn:aws:iam::123456789012:role/ReadOnly"
+        ));
+    }
+
+    #[test]
+    fn any_pattern_in_the_list_matches() {
+        let filter = RoleFilter::parse(" */Admin , ,regex:Dev$ ").unwrap();
+        let aliases = HashMap::new();
+
+        assert!(filter.matches(&role("arn:aws:iam::123456789012:role/Admin"), &aliases));
+        assert!(filter.matches(&role("arn:aws:iam::123456789012:role/Dev"), &aliases));
+        assert!(!filter.matches(&role("arn:aws:iam::123456789012:role/ReadOnly"), &aliases));
+    }
+
+    #[test]
+    fn matches_account_aliases() {
+        let filter = RoleFilter::parse("prod-*/Admin").unwrap();
+        let aliases = HashMap::from([("123456789012".to_string(), "prod-web".to_string())]);
+        let admin = role("arn:aws:iam::123456789012:role/Admin");
+
+        assert!(filter.matches(&admin, &aliases));
+        assert!(!filter.matches(&admin, &HashMap::new()));
+        assert!(!filter.matches(&role("arn:aws:iam::210987654321:role/Admin"), &aliases));
+    }
+
+    #[test]
+    fn matches_role_names_without_their_path() {
+        assert!(matches(
+            "123456789012/Admin",
+            "arn:aws:iam::123456789012:role/team/Admin"
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let error = RoleFilter::parse("*/Admin,regex:(unclosed").unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("Invalid azure_role_filter pattern 'regex:(unclosed'"));
+    }
+}
@@ -1,61 +1,450 @@
+use crate::cloud::saml_endpoint;
+use crate::partition::AwsPartition;
 use anyhow::anyhow;
 use anyhow::Result;
 use base64::engine::general_purpose;
 use base64::Engine;
-use chrono::Utc;
+use chrono::{DateTime, SubsecRound, Utc};
 use file_manager::aws_config::AwsConfig;
+use file_manager::clock_skew::ClockSkew;
+use flate2::read::DeflateDecoder;
 use flate2::write::DeflateEncoder;
 use flate2::Compression;
-use std::io::Write;
-use url::form_urlencoded;
+use serde::{Deserialize, Serialize};
+use serde_xml_rs::from_str;
+use std::io::{Read, Write};
+use url::Url;
 use uuid::Uuid;
 
-pub fn create_login_url(config: &AwsConfig) -> Result<String> {
-    let assertion_consumer_service_url = match &config.region {
-        Some(r) if r.starts_with("us-gov") => "https://signin.amazonaws-us-gov.com/saml",
-        Some(r) if r.starts_with("cn-") => "https://signin.amazonaws.cn/saml",
-        _ => "https://signin.aws.amazon.com/saml",
+pub const NAME_ID_FORMAT_EMAIL: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuthnRequest {
+    pub id: String,
+    pub issue_instant: DateTime<Utc>,
+    pub issuer: String,
+    pub assertion_consumer_service_url: String,
+    pub force_authn: bool,
+    pub is_passive: bool,
+    pub name_id_policy_format: Option<String>,
+    pub requested_authn_context: Option<RequestedAuthnContext>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RequestedAuthnContext {
+    pub comparison: String,
+    pub class_refs: Vec<String>,
+}
+
+pub struct AuthnRequestBuilder {
+    request: AuthnRequest,
+}
+
+impl AuthnRequestBuilder {
+    pub fn id(mut self, id: &str) -> Self {
+        self.request.id = id.to_string();
+        self
+    }
+
+    pub fn issue_instant(mut self, issue_instant: DateTime<Utc>) -> Self {
+        self.request.issue_instant = issue_instant.trunc_subsecs(0);
+        self
+    }
+
+    pub fn force_authn(mut self, force_authn: bool) -> Self {
+        self.request.force_authn = force_authn;
+        self
+    }
+
+    pub fn is_passive(mut self, is_passive: bool) -> Self {
+        self.request.is_passive = is_passive;
+        self
+    }
+
+    pub fn name_id_policy_format(mut self, format: Option<String>) -> Self {
+        self.request.name_id_policy_format = format;
+        self
+    }
+
+    pub fn requested_authn_context(mut self, context: Option<RequestedAuthnContext>) -> Self {
+        self.request.requested_authn_context = context;
+        self
+    }
+
+    pub fn build(self) -> AuthnRequest {
+        self.request
+    }
+}
+
+impl AuthnRequest {
+    pub fn builder(issuer: &str, assertion_consumer_service_url: &str) -> AuthnRequestBuilder {
+        AuthnRequestBuilder {
+            request: AuthnRequest {
+                id: format!("id{}", Uuid::new_v4()),
+                issue_instant: ClockSkew::now().trunc_subsecs(0),
+                issuer: issuer.to_string(),
+                assertion_consumer_service_url: assertion_consumer_service_url.to_string(),
+                force_authn: false,
+                is_passive: false,
+                name_id_policy_format: Some(NAME_ID_FORMAT_EMAIL.to_string()),
+                requested_authn_context: None,
+            },
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = format!(
+            r#"<samlp:AuthnRequest xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="{}" Version="2.0" IssueInstant="{}" ForceAuthn="{}" IsPassive="{}" AssertionConsumerServiceURL="{}">"#,
+            escape_xml(&self.id),
+            self.issue_instant.format("%Y-%m-%dT%H:%M:%SZ"),
+            self.force_authn,
+            self.is_passive,
+            escape_xml(&self.assertion_consumer_service_url),
+        );
+
+        xml.push_str(&format!(
+            "<saml:Issuer>{}</saml:Issuer>",
+            escape_xml(&self.issuer)
+        ));
+
+        if let Some(format) = &self.name_id_policy_format {
+            xml.push_str(&format!(
+                r#"<samlp:NameIDPolicy Format="{}"/>"#,
+                escape_xml(format)
+            ));
+        }
+
+        if let Some(context) = &self.requested_authn_context {
+            xml.push_str(&format!(
+                r#"<samlp:RequestedAuthnContext Comparison="{}">"#,
+                escape_xml(&context.comparison)
+            ));
+            for class_ref in &context.class_refs {
+                xml.push_str(&format!(
+                    "<saml:AuthnContextClassRef>{}</saml:AuthnContextClassRef>",
+                    escape_xml(class_ref)
+                ));
+            }
+            xml.push_str("</samlp:RequestedAuthnContext>");
+        }
+
+        xml.push_str("</samlp:AuthnRequest>");
+
+        xml
+    }
+
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        let parsed: AuthnRequestXml = from_str(xml)?;
+
+        Ok(Self {
+            id: parsed.id,
+            issue_instant: DateTime::parse_from_rfc3339(&parsed.issue_instant)?.with_timezone(&Utc),
+            issuer: parsed.issuer,
+            assertion_consumer_service_url: parsed
+                .assertion_consumer_service_url
+                .unwrap_or_default(),
+            force_authn: parsed.force_authn.unwrap_or_default(),
+            is_passive: parsed.is_passive.unwrap_or_default(),
+            name_id_policy_format: parsed.name_id_policy.and_then(|policy| policy.format),
+            requested_authn_context: parsed.requested_authn_context.map(|context| {
+                RequestedAuthnContext {
+                    comparison: context.comparison.unwrap_or("exact".to_string()),
+                    class_refs: context.class_refs,
+                }
+            }),
+        })
+    }
+
+    /// Deflates and base64 encodes the request for the HTTP-Redirect binding
+    pub fn encode(&self) -> Result<String> {
+        let compressed_bytes = compress(&self.to_xml())?;
+        Ok(general_purpose::STANDARD.encode(compressed_bytes))
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self> {
+        Self::from_xml(&decode_saml_request(encoded)?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LoginUrl {
+    pub endpoint: String,
+    pub authn_request: AuthnRequest,
+    pub relay_state: Option<String>,
+    pub login_hint: Option<String>,
+    pub domain_hint: Option<String>,
+    pub prompt: Option<String>,
+}
+
+impl LoginUrl {
+    pub fn to_url(&self) -> Result<String> {
+        let mut url = Url::parse(&self.endpoint)?;
+
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("SAMLRequest", &self.authn_request.encode()?);
+            if let Some(relay_state) = &self.relay_state {
+                query.append_pair("RelayState", relay_state);
+            }
+            if let Some(login_hint) = &self.login_hint {
+                query.append_pair("login_hint", login_hint);
+            }
+            if let Some(domain_hint) = &self.domain_hint {
+                query.append_pair("domain_hint", domain_hint);
+            }
+            if let Some(prompt) = &self.prompt {
+                query.append_pair("prompt", prompt);
+            }
+        }
+
+        Ok(url.to_string())
+    }
+
+    pub fn parse(url: &str) -> Result<Self> {
+        let mut url = Url::parse(url)?;
+
+        let mut saml_request = None;
+        let mut relay_state = None;
+        let mut login_hint = None;
+        let mut domain_hint = None;
+        let mut prompt = None;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "SAMLRequest" => saml_request = Some(value.into_owned()),
+                "RelayState" => relay_state = Some(value.into_owned()),
+                "login_hint" => login_hint = Some(value.into_owned()),
+                "domain_hint" => domain_hint = Some(value.into_owned()),
+                "prompt" => prompt = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        url.set_query(None);
+
+        Ok(Self {
+            endpoint: url.to_string(),
+            authn_request: AuthnRequest::decode(
+                &saml_request.ok_or(anyhow!("SAMLRequest not found in the URL"))?,
+            )?,
+            relay_state,
+            login_hint,
+            domain_hint,
+            prompt,
+        })
+    }
+}
+
+pub fn assertion_consumer_service_url(config: &AwsConfig) -> String {
+    match &config.azure_acs_url {
+        Some(url) => url.to_owned(),
+        None => AwsPartition::from_config(config).signin_url(
+            config.region.as_deref(),
+            config.azure_regional_signin == Some(true),
+        ),
+    }
+}
+
+pub fn create_login_request(config: &AwsConfig) -> Result<LoginUrl> {
+    let assertion_consumer_service_url = assertion_consumer_service_url(config);
+
+    let azure_app_id_uri = config
+        .azure_app_id_uri
+        .as_ref()
+        .ok_or(anyhow!("azure_app_id_uri not set"))?;
+
+    let name_id_policy_format = match &config.azure_name_id_format {
+        Some(format) if format == "none" => None,
+        Some(format) => Some(format.to_owned()),
+        None => Some(NAME_ID_FORMAT_EMAIL.to_string()),
     };
 
-    let saml_request = format!(
-        r#"
-        <samlp:AuthnRequest xmlns="urn:oasis:names:tc:SAML:2.0:metadata" ID="id{}" Version="2.0" IssueInstant="{}" IsPassive="false" AssertionConsumerServiceURL="{}" xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol">
-            <Issuer xmlns="urn:oasis:names:tc:SAML:2.0:assertion">{}</Issuer>
-            <samlp:NameIDPolicy Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress"></samlp:NameIDPolicy>
-        </samlp:AuthnRequest>
-        "#,
-        Uuid::new_v4(),
-        Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
-        assertion_consumer_service_url,
+    let requested_authn_context =
         config
-            .azure_app_id_uri
+            .azure_authn_context
             .as_ref()
-            .ok_or(anyhow!("azure_app_id_uri not set"))?,
-    );
+            .map(|class_refs| RequestedAuthnContext {
+                comparison: config
+                    .azure_authn_context_comparison
+                    .clone()
+                    .unwrap_or("exact".to_string()),
+                class_refs: class_refs
+                    .split(',')
+                    .map(|class_ref| class_ref.trim().to_string())
+                    .filter(|class_ref| !class_ref.is_empty())
+                    .collect(),
+            });
 
-    let compressed_bytes = compress_and_encode(&saml_request)?;
-    let saml_base64_encoded = base64_url_encode(&compressed_bytes);
+    let authn_request = AuthnRequest::builder(azure_app_id_uri, &assertion_consumer_service_url)
+        .force_authn(config.azure_force_authn.unwrap_or_default())
+        .is_passive(config.azure_is_passive.unwrap_or_default())
+        .name_id_policy_format(name_id_policy_format)
+        .requested_authn_context(requested_authn_context)
+        .build();
 
-    let url = format!(
-        "https://login.microsoftonline.com/{}/saml2?SAMLRequest={}",
-        config
-            .azure_tenant_id
-            .as_ref()
-            .ok_or(anyhow!("azure_tenant_id not set"))?,
-        saml_base64_encoded
-    );
+    // Hinting the remembered username would skip the account picker we were asked to show
+    let (login_hint, prompt) = if config.azure_select_account == Some(true) {
+        (None, Some("select_account".to_string()))
+    } else {
+        (config.azure_default_username.clone(), None)
+    };
+
+    Ok(LoginUrl {
+        endpoint: saml_endpoint(config)?,
+        authn_request,
+        relay_state: config.azure_relay_state.clone(),
+        login_hint,
+        domain_hint: config.azure_domain_hint.clone(),
+        prompt,
+    })
+}
 
-    Ok(url)
+/// Base64 decodes and inflates a SAMLRequest from the HTTP-Redirect binding
+pub fn decode_saml_request(encoded: &str) -> Result<String> {
+    let compressed_bytes = general_purpose::STANDARD.decode(encoded.trim())?;
+    let mut decoder = DeflateDecoder::new(compressed_bytes.as_slice());
+    let mut xml = String::new();
+    decoder.read_to_string(&mut xml)?;
+    Ok(xml)
 }
 
-fn compress_and_encode(string: &str) -> Result<Vec<u8>> {
+fn compress(string: &str) -> Result<Vec<u8>> {
     let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
     encoder.write_all(string.as_bytes())?;
     Ok(encoder.finish()?)
 }
 
-pub fn base64_url_encode(bytes: &[u8]) -> String {
-    let mut output = String::new();
-    general_purpose::STANDARD.encode_string(bytes, &mut output);
-    form_urlencoded::byte_serialize(output.as_bytes()).collect()
+pub fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthnRequestXml {
+    #[serde(rename = "ID")]
+    id: String,
+    #[serde(rename = "IssueInstant")]
+    issue_instant: String,
+    #[serde(rename = "ForceAuthn")]
+    force_authn: Option<bool>,
+    #[serde(rename = "IsPassive")]
+    is_passive: Option<bool>,
+    #[serde(rename = "AssertionConsumerServiceURL")]
+    assertion_consumer_service_url: Option<String>,
+    #[serde(rename = "Issuer")]
+    issuer: String,
+    #[serde(rename = "NameIDPolicy")]
+    name_id_policy: Option<NameIdPolicyXml>,
+    #[serde(rename = "RequestedAuthnContext")]
+    requested_authn_context: Option<RequestedAuthnContextXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NameIdPolicyXml {
+    #[serde(rename = "Format")]
+    format: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RequestedAuthnContextXml {
+    #[serde(rename = "Comparison")]
+    comparison: Option<String>,
+    #[serde(rename = "AuthnContextClassRef", default)]
+    class_refs: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AwsConfig {
+        AwsConfig {
+            azure_tenant_id: Some("11111111-2222-3333-4444-555555555555".to_string()),
+            azure_app_id_uri: Some("https://signin.aws.amazon.com/saml?a=1&b=<2>".to_string()),
+            azure_default_username: Some("o'brien@corp.com".to_string()),
+            azure_domain_hint: Some("corp.com".to_string()),
+            azure_relay_state: Some(
+                "https://console.aws.amazon.com/?region=\"us-east-1\"".to_string(),
+            ),
+            azure_authn_context: Some(
+                "urn:oasis:names:tc:SAML:2.0:ac:classes:Password & MFA, urn:custom".to_string(),
+            ),
+            azure_authn_context_comparison: Some("minimum".to_string()),
+            azure_force_authn: Some(true),
+            ..AwsConfig::default()
+        }
+    }
+
+    #[test]
+    fn login_url_round_trips() {
+        let login_request = create_login_request(&config()).unwrap();
+        let url = login_request.to_url().unwrap();
+
+        let parsed = LoginUrl::parse(&url).unwrap();
+
+        assert_eq!(parsed, login_request);
+        assert_eq!(
+            parsed.authn_request.issuer,
+            "https://signin.aws.amazon.com/saml?a=1&b=<2>"
+        );
+        assert_eq!(parsed.login_hint.as_deref(), Some("o'brien@corp.com"));
+        assert_eq!(
+            parsed.authn_request.requested_authn_context,
+            Some(RequestedAuthnContext {
+                comparison: "minimum".to_string(),
+                class_refs: vec![
+                    "urn:oasis:names:tc:SAML:2.0:ac:classes:Password & MFA".to_string(),
+                    "urn:custom".to_string(),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn request_xml_is_escaped() {
+        let login_request = create_login_request(&config()).unwrap();
+        let encoded = login_request.authn_request.encode().unwrap();
+
+        let xml = decode_saml_request(&encoded).unwrap();
+
+        assert!(xml.contains(
+            "<saml:Issuer>https://signin.aws.amazon.com/saml?a=1&amp;b=&lt;2&gt;</saml:Issuer>"
+        ));
+        assert!(xml.contains("Password &amp; MFA"));
+        assert_eq!(
+            AuthnRequest::decode(&encoded).unwrap(),
+            login_request.authn_request
+        );
+    }
+
+    #[test]
+    fn select_account_drops_the_login_hint() {
+        let config = AwsConfig {
+            azure_select_account: Some(true),
+            ..config()
+        };
+
+        let parsed =
+            LoginUrl::parse(&create_login_request(&config).unwrap().to_url().unwrap()).unwrap();
+
+        assert_eq!(parsed.login_hint, None);
+        assert_eq!(parsed.prompt.as_deref(), Some("select_account"));
+    }
+
+    #[test]
+    fn escape_xml_escapes_markup() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+    }
 }
@@ -1,82 +1,748 @@
 use crate::helpers::base64_decode_to_string;
-use anyhow::{bail, Result};
-use serde::Deserialize;
+use crate::saml_response::decryption::DecryptionKey;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use serde_xml_rs::from_str;
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::str;
 
-#[derive(Clone, Debug)]
+mod c14n;
+pub mod decryption;
+pub mod verification;
+
+pub const ROLE_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/Role";
+pub const ROLE_SESSION_NAME_ATTRIBUTE: &str =
+    "https://aws.amazon.com/SAML/Attributes/RoleSessionName";
+pub const SESSION_DURATION_ATTRIBUTE: &str =
+    "https://aws.amazon.com/SAML/Attributes/SessionDuration";
+pub const SOURCE_IDENTITY_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/SourceIdentity";
+pub const TRANSITIVE_TAG_KEYS_ATTRIBUTE: &str =
+    "https://aws.amazon.com/SAML/Attributes/TransitiveTagKeys";
+pub const PRINCIPAL_TAG_ATTRIBUTE_PREFIX: &str =
+    "https://aws.amazon.com/SAML/Attributes/PrincipalTag:";
+
+#[derive(Clone, Debug, Serialize)]
 pub struct Role {
     pub role_arn: String,
     pub principal_arn: String,
 }
 
+impl Role {
+    /// Parses a `Role` attribute value, IdPs list the role and principal ARNs in either order
+    pub fn parse(value: &str) -> Result<Self> {
+        let parts: Vec<&str> = value.split(',').collect();
+        if parts.len() < 2 {
+            bail!("Malformed role data");
+        }
+
+        let role = if parts[0].contains(":role/") {
+            Role {
+                role_arn: parts[0].trim().to_string(),
+                principal_arn: parts[1].trim().to_string(),
+            }
+        } else {
+            Role {
+                role_arn: parts[1].trim().to_string(),
+                principal_arn: parts[0].trim().to_string(),
+            }
+        };
+
+        Ok(role)
+    }
+
+    /// The account ID from the role ARN
+    pub fn account_id(&self) -> &str {
+        self.role_arn.split(':').nth(4).unwrap_or_default()
+    }
+
+    /// The role name from the role ARN, without its path
+    pub fn role_name(&self) -> &str {
+        self.role_arn.rsplit('/').next().unwrap_or_default()
+    }
+}
+
 impl Display for Role {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} ({})", self.role_arn, self.principal_arn)
     }
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct SamlResponse {
+    pub id: Option<String>,
+    pub destination: Option<String>,
+    pub in_response_to: Option<String>,
+    pub issue_instant: Option<DateTime<Utc>>,
+    pub issuer: Option<String>,
+    pub status_code: Option<String>,
+    pub assertion: SamlAssertion,
+}
+
+/// The `Response` around the assertion, readable on its own as Azure AD error responses have no assertion
+#[derive(Clone, Debug, Serialize)]
+pub struct ResponseEnvelope {
+    pub id: Option<String>,
+    pub destination: Option<String>,
+    pub in_response_to: Option<String>,
+    pub issue_instant: Option<DateTime<Utc>>,
+    pub issuer: Option<String>,
+    pub status_code: Option<String>,
+    pub sub_status_code: Option<String>,
+    pub status_message: Option<String>,
+    pub assertion: AssertionKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssertionKind {
+    Plain,
+    Encrypted,
+    Absent,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SamlAssertion {
+    pub id: Option<String>,
+    pub issue_instant: Option<DateTime<Utc>>,
+    pub issuer: String,
+    pub subject: Option<Subject>,
+    pub conditions: Option<Conditions>,
+    pub authn_statement: Option<AuthnStatement>,
+    pub attributes: Vec<SamlAttribute>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Subject {
+    pub name_id: String,
+    pub name_id_format: Option<String>,
+    pub recipient: Option<String>,
+    pub in_response_to: Option<String>,
+    pub not_on_or_after: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Conditions {
+    pub not_before: Option<DateTime<Utc>>,
+    pub not_on_or_after: Option<DateTime<Utc>>,
+    pub audiences: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuthnStatement {
+    pub authn_instant: Option<DateTime<Utc>>,
+    pub session_index: Option<String>,
+    pub session_not_on_or_after: Option<DateTime<Utc>>,
+    pub authn_context_class_ref: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SamlAttribute {
+    pub name: String,
+    pub friendly_name: Option<String>,
+    pub values: Vec<String>,
+}
+
+impl SamlResponse {
+    pub fn from_base64(saml_response: &str) -> Result<Self> {
+        Self::from_xml(&base64_decode_to_string(saml_response)?)
+    }
+
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        Self::from_xml_with_key(xml, None)
+    }
+
+    pub fn from_base64_with_key(
+        saml_response: &str,
+        decryption_key: Option<&DecryptionKey>,
+    ) -> Result<Self> {
+        Self::from_xml_with_key(&base64_decode_to_string(saml_response)?, decryption_key)
+    }
+
+    /// Parses the response, decrypting an `EncryptedAssertion` with the key when there is one
+    pub fn from_xml_with_key(xml: &str, decryption_key: Option<&DecryptionKey>) -> Result<Self> {
+        let response: ResponseXml = from_str(xml)?;
+
+        let assertion = match (response.assertion, &response.encrypted_assertion) {
+            (Some(assertion), _) => assertion,
+            (None, Some(encrypted_assertion)) => {
+                let decryption_key = decryption_key.ok_or(anyhow!(
+                    "The SAML assertion is encrypted, set azure_saml_decryption_key to read it"
+                ))?;
+                from_str(&decryption::decrypt(encrypted_assertion, decryption_key)?)?
+            }
+            (None, None) => bail!("No Assertion found in the SAML response"),
+        };
+
+        Ok(Self {
+            id: response.id,
+            destination: response.destination,
+            in_response_to: response.in_response_to,
+            issue_instant: parse_instant(response.issue_instant)?,
+            issuer: response.issuer,
+            status_code: response
+                .status
+                .and_then(|status| status.status_code)
+                .map(|code| code.value),
+            assertion: assertion.try_into()?,
+        })
+    }
+}
+
+impl ResponseEnvelope {
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        let response: ResponseXml = from_str(xml)?;
+
+        let assertion = match (&response.assertion, &response.encrypted_assertion) {
+            (Some(_), _) => AssertionKind::Plain,
+            (None, Some(_)) => AssertionKind::Encrypted,
+            (None, None) => AssertionKind::Absent,
+        };
+
+        let (status_code, status_message) = match response.status {
+            Some(status) => (status.status_code, status.status_message),
+            None => (None, None),
+        };
+
+        Ok(Self {
+            id: response.id,
+            destination: response.destination,
+            in_response_to: response.in_response_to,
+            issue_instant: parse_instant(response.issue_instant)?,
+            issuer: response.issuer.map(|issuer| issuer.trim().to_string()),
+            sub_status_code: status_code
+                .as_ref()
+                .and_then(|code| code.status_code.as_ref())
+                .map(|code| code.value.clone()),
+            status_code: status_code.map(|code| code.value),
+            status_message: status_message.map(|message| message.trim().to_string()),
+            assertion,
+        })
+    }
+}
+
+impl SamlAssertion {
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        let assertion: AssertionXml = from_str(xml)?;
+        assertion.try_into()
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&SamlAttribute> {
+        self.attributes.iter().find(|attr| attr.name == name)
+    }
+
+    fn first_value(&self, name: &str) -> Option<&str> {
+        self.attribute(name)
+            .and_then(|attr| attr.values.first())
+            .map(|value| value.as_str())
+    }
+
+    pub fn roles(&self) -> Result<Vec<Role>> {
+        self.attributes
+            .iter()
+            .filter(|attr| attr.name == ROLE_ATTRIBUTE)
+            .flat_map(|attr| attr.values.iter())
+            .map(|value| Role::parse(value))
+            .collect()
+    }
+
+    pub fn role_session_name(&self) -> Option<&str> {
+        self.first_value(ROLE_SESSION_NAME_ATTRIBUTE)
+    }
+
+    /// Session duration in seconds
+    pub fn session_duration(&self) -> Option<u32> {
+        self.first_value(SESSION_DURATION_ATTRIBUTE)
+            .and_then(|value| value.trim().parse().ok())
+    }
+
+    pub fn source_identity(&self) -> Option<&str> {
+        self.first_value(SOURCE_IDENTITY_ATTRIBUTE)
+    }
+
+    pub fn principal_tags(&self) -> BTreeMap<String, String> {
+        self.attributes
+            .iter()
+            .filter_map(|attr| {
+                let key = attr.name.strip_prefix(PRINCIPAL_TAG_ATTRIBUTE_PREFIX)?;
+                Some((key.to_string(), attr.values.first()?.to_owned()))
+            })
+            .collect()
+    }
+
+    pub fn transitive_tag_keys(&self) -> Vec<String> {
+        self.attribute(TRANSITIVE_TAG_KEYS_ATTRIBUTE)
+            .map(|attr| attr.values.clone())
+            .unwrap_or_default()
+    }
+}
+
+pub fn parse_roles_from_saml_response(assertion: &str) -> Result<Vec<Role>> {
+    SamlResponse::from_base64(assertion)?.assertion.roles()
+}
+
+fn parse_instant(value: Option<String>) -> Result<Option<DateTime<Utc>>> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value.trim())
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| anyhow!("Failed to parse SAML instant '{}': {:?}", value, e))
+        })
+        .transpose()
+}
+
+impl TryFrom<AssertionXml> for SamlAssertion {
+    type Error = anyhow::Error;
+
+    fn try_from(assertion: AssertionXml) -> Result<Self> {
+        let subject = match assertion.subject {
+            Some(subject) => {
+                let confirmation_data = subject
+                    .subject_confirmation
+                    .and_then(|confirmation| confirmation.subject_confirmation_data);
+                let (recipient, in_response_to, not_on_or_after) = match confirmation_data {
+                    Some(data) => (
+                        data.recipient,
+                        data.in_response_to,
+                        parse_instant(data.not_on_or_after)?,
+                    ),
+                    None => (None, None, None),
+                };
+
+                Some(Subject {
+                    name_id: subject
+                        .name_id
+                        .as_ref()
+                        .map(|name_id| name_id.value.trim().to_string())
+                        .unwrap_or_default(),
+                    name_id_format: subject.name_id.and_then(|name_id| name_id.format),
+                    recipient,
+                    in_response_to,
+                    not_on_or_after,
+                })
+            }
+            None => None,
+        };
+
+        let conditions = match assertion.conditions {
+            Some(conditions) => Some(Conditions {
+                not_before: parse_instant(conditions.not_before)?,
+                not_on_or_after: parse_instant(conditions.not_on_or_after)?,
+                audiences: conditions
+                    .audience_restriction
+                    .into_iter()
+                    .flat_map(|restriction| restriction.audience)
+                    .map(|audience| audience.trim().to_string())
+                    .collect(),
+            }),
+            None => None,
+        };
+
+        let authn_statement = match assertion.authn_statement {
+            Some(statement) => Some(AuthnStatement {
+                authn_instant: parse_instant(statement.authn_instant)?,
+                session_index: statement.session_index,
+                session_not_on_or_after: parse_instant(statement.session_not_on_or_after)?,
+                authn_context_class_ref: statement
+                    .authn_context
+                    .and_then(|context| context.authn_context_class_ref)
+                    .map(|class_ref| class_ref.trim().to_string()),
+            }),
+            None => None,
+        };
+
+        let attributes = assertion
+            .attribute_statement
+            .into_iter()
+            .flat_map(|statement| statement.attribute)
+            .map(|attr| SamlAttribute {
+                name: attr.name,
+                friendly_name: attr.friendly_name,
+                values: attr
+                    .attribute_value
+                    .into_iter()
+                    .map(|value| value.value.trim().to_string())
+                    .collect(),
+            })
+            .collect();
+
+        Ok(Self {
+            id: assertion.id,
+            issue_instant: parse_instant(assertion.issue_instant)?,
+            issuer: assertion.issuer.unwrap_or_default().trim().to_string(),
+            subject,
+            conditions,
+            authn_statement,
+            attributes,
+        })
+    }
+}
+
 #[derive(Deserialize, Debug)]
-struct SamlResponse {
+struct ResponseXml {
+    #[serde(rename = "ID")]
+    id: Option<String>,
+    #[serde(rename = "Destination")]
+    destination: Option<String>,
+    #[serde(rename = "InResponseTo")]
+    in_response_to: Option<String>,
+    #[serde(rename = "IssueInstant")]
+    issue_instant: Option<String>,
+    #[serde(rename = "Issuer")]
+    issuer: Option<String>,
+    #[serde(rename = "Status")]
+    status: Option<StatusXml>,
+    #[serde(rename = "Signature")]
+    signature: Option<SignatureXml>,
     #[serde(rename = "Assertion")]
-    assertion: Assertion,
+    assertion: Option<AssertionXml>,
+    #[serde(rename = "EncryptedAssertion")]
+    encrypted_assertion: Option<EncryptedAssertionXml>,
 }
 
 #[derive(Deserialize, Debug)]
-struct Assertion {
+struct StatusXml {
+    #[serde(rename = "StatusCode")]
+    status_code: Option<StatusCodeXml>,
+    #[serde(rename = "StatusMessage")]
+    status_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StatusCodeXml {
+    #[serde(rename = "Value")]
+    value: String,
+    #[serde(rename = "StatusCode")]
+    status_code: Option<Box<StatusCodeXml>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AssertionXml {
+    #[serde(rename = "ID")]
+    id: Option<String>,
+    #[serde(rename = "IssueInstant")]
+    issue_instant: Option<String>,
+    #[serde(rename = "Issuer")]
+    issuer: Option<String>,
+    #[serde(rename = "Signature")]
+    signature: Option<SignatureXml>,
+    #[serde(rename = "Subject")]
+    subject: Option<SubjectXml>,
+    #[serde(rename = "Conditions")]
+    conditions: Option<ConditionsXml>,
+    #[serde(rename = "AuthnStatement")]
+    authn_statement: Option<AuthnStatementXml>,
     #[serde(rename = "AttributeStatement")]
-    attribute_statement: AttributeStatement,
+    attribute_statement: Option<AttributeStatementXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SubjectXml {
+    #[serde(rename = "NameID")]
+    name_id: Option<NameIdXml>,
+    #[serde(rename = "SubjectConfirmation")]
+    subject_confirmation: Option<SubjectConfirmationXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NameIdXml {
+    #[serde(rename = "Format")]
+    format: Option<String>,
+    #[serde(rename = "$value", default)]
+    value: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SubjectConfirmationXml {
+    #[serde(rename = "SubjectConfirmationData")]
+    subject_confirmation_data: Option<SubjectConfirmationDataXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SubjectConfirmationDataXml {
+    #[serde(rename = "InResponseTo")]
+    in_response_to: Option<String>,
+    #[serde(rename = "NotOnOrAfter")]
+    not_on_or_after: Option<String>,
+    #[serde(rename = "Recipient")]
+    recipient: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ConditionsXml {
+    #[serde(rename = "NotBefore")]
+    not_before: Option<String>,
+    #[serde(rename = "NotOnOrAfter")]
+    not_on_or_after: Option<String>,
+    #[serde(rename = "AudienceRestriction", default)]
+    audience_restriction: Vec<AudienceRestrictionXml>,
 }
 
 #[derive(Deserialize, Debug)]
-struct AttributeStatement {
-    #[serde(rename = "Attribute")]
-    attribute: Vec<Attribute>,
+struct AudienceRestrictionXml {
+    #[serde(rename = "Audience", default)]
+    audience: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
-struct Attribute {
+struct AuthnStatementXml {
+    #[serde(rename = "AuthnInstant")]
+    authn_instant: Option<String>,
+    #[serde(rename = "SessionIndex")]
+    session_index: Option<String>,
+    #[serde(rename = "SessionNotOnOrAfter")]
+    session_not_on_or_after: Option<String>,
+    #[serde(rename = "AuthnContext")]
+    authn_context: Option<AuthnContextXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthnContextXml {
+    #[serde(rename = "AuthnContextClassRef")]
+    authn_context_class_ref: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AttributeStatementXml {
+    #[serde(rename = "Attribute", default)]
+    attribute: Vec<AttributeXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AttributeXml {
     #[serde(rename = "Name")]
     name: String,
-    #[serde(rename = "AttributeValue")]
-    attribute_value: Vec<AttributeValue>,
+    #[serde(rename = "FriendlyName")]
+    friendly_name: Option<String>,
+    #[serde(rename = "AttributeValue", default)]
+    attribute_value: Vec<AttributeValueXml>,
 }
 
 #[derive(Deserialize, Debug)]
-struct AttributeValue {
-    #[serde(rename = "$value")]
+struct AttributeValueXml {
+    #[serde(rename = "$value", default)]
     value: String,
 }
 
-pub fn parse_roles_from_saml_response(assertion: &str) -> Result<Vec<Role>> {
-    let decoded_str = base64_decode_to_string(assertion)?;
-
-    let saml_response: SamlResponse = from_str(&decoded_str)?;
-
-    let mut roles = Vec::new();
-    for attr in saml_response.assertion.attribute_statement.attribute {
-        if attr.name == "https://aws.amazon.com/SAML/Attributes/Role" {
-            for val in attr.attribute_value {
-                let parts: Vec<&str> = val.value.split(',').collect();
-                if parts.len() < 2 {
-                    bail!("Malformed role data");
-                }
-                let role = if parts[0].contains(":role/") {
-                    Role {
-                        role_arn: parts[0].trim().to_string(),
-                        principal_arn: parts[1].trim().to_string(),
-                    }
-                } else {
-                    Role {
-                        role_arn: parts[1].trim().to_string(),
-                        principal_arn: parts[0].trim().to_string(),
-                    }
-                };
-                roles.push(role);
-            }
+#[derive(Deserialize, Debug)]
+struct SignatureXml {
+    #[serde(rename = "SignedInfo")]
+    signed_info: SignedInfoXml,
+    #[serde(rename = "SignatureValue")]
+    signature_value: String,
+    #[serde(rename = "KeyInfo")]
+    key_info: Option<KeyInfoXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignedInfoXml {
+    #[serde(rename = "CanonicalizationMethod")]
+    canonicalization_method: AlgorithmXml,
+    #[serde(rename = "SignatureMethod")]
+    signature_method: AlgorithmXml,
+    #[serde(rename = "Reference")]
+    reference: ReferenceXml,
+}
+
+#[derive(Deserialize, Debug)]
+struct AlgorithmXml {
+    #[serde(rename = "Algorithm")]
+    algorithm: String,
+    #[serde(rename = "InclusiveNamespaces")]
+    inclusive_namespaces: Option<InclusiveNamespacesXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InclusiveNamespacesXml {
+    #[serde(rename = "PrefixList")]
+    prefix_list: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReferenceXml {
+    #[serde(rename = "URI")]
+    uri: String,
+    #[serde(rename = "Transforms")]
+    transforms: TransformsXml,
+    #[serde(rename = "DigestMethod")]
+    digest_method: AlgorithmXml,
+    #[serde(rename = "DigestValue")]
+    digest_value: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TransformsXml {
+    #[serde(rename = "Transform", default)]
+    transform: Vec<AlgorithmXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct KeyInfoXml {
+    #[serde(rename = "X509Data")]
+    x509_data: Option<X509DataXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct X509DataXml {
+    #[serde(rename = "X509Certificate")]
+    x509_certificate: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EncryptedAssertionXml {
+    #[serde(rename = "EncryptedData")]
+    encrypted_data: EncryptedDataXml,
+    #[serde(rename = "EncryptedKey", default)]
+    encrypted_key: Vec<EncryptedKeyXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EncryptedDataXml {
+    #[serde(rename = "EncryptionMethod")]
+    encryption_method: AlgorithmXml,
+    #[serde(rename = "KeyInfo")]
+    key_info: Option<EncryptedDataKeyInfoXml>,
+    #[serde(rename = "CipherData")]
+    cipher_data: CipherDataXml,
+}
+
+#[derive(Deserialize, Debug)]
+struct EncryptedDataKeyInfoXml {
+    #[serde(rename = "EncryptedKey")]
+    encrypted_key: Option<EncryptedKeyXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EncryptedKeyXml {
+    #[serde(rename = "EncryptionMethod")]
+    encryption_method: KeyEncryptionMethodXml,
+    #[serde(rename = "CipherData")]
+    cipher_data: CipherDataXml,
+}
+
+#[derive(Deserialize, Debug)]
+struct KeyEncryptionMethodXml {
+    #[serde(rename = "Algorithm")]
+    algorithm: String,
+    #[serde(rename = "DigestMethod")]
+    digest_method: Option<AlgorithmXml>,
+    #[serde(rename = "MGF")]
+    mgf: Option<AlgorithmXml>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CipherDataXml {
+    #[serde(rename = "CipherValue")]
+    cipher_value: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The same response with the assertion in the default namespace or under a prefix
+    fn response_xml(prefix: &str) -> String {
+        let declaration = match prefix {
+            "" => r#"xmlns="urn:oasis:names:tc:SAML:2.0:assertion""#.to_string(),
+            prefix => format!(
+                r#"xmlns:{}="urn:oasis:names:tc:SAML:2.0:assertion""#,
+                prefix
+            ),
+        };
+        let p = match prefix {
+            "" => String::new(),
+            prefix => format!("{}:", prefix),
+        };
+
+        format!(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" ID="_response" Version="2.0" IssueInstant="2023-09-14T03:12:45.123Z" Destination="https://signin.aws.amazon.com/saml" InResponseTo="id123">
+  <{p}Issuer {declaration}>https://sts.windows.net/tenant/</{p}Issuer>
+  <samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status>
+  <{p}Assertion {declaration} ID="_assertion" IssueInstant="2023-09-14T03:12:45.120Z" Version="2.0">
+    <{p}Issuer>https://sts.windows.net/tenant/</{p}Issuer>
+    <{p}Subject>
+      <{p}NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">user@corp.com</{p}NameID>
+      <{p}SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer">
+        <{p}SubjectConfirmationData InResponseTo="id123" NotOnOrAfter="2023-09-14T03:17:45.120Z" Recipient="https://signin.aws.amazon.com/saml"/>
+      </{p}SubjectConfirmation>
+    </{p}Subject>
+    <{p}Conditions NotBefore="2023-09-14T03:07:45.120Z" NotOnOrAfter="2023-09-14T04:12:45.120Z">
+      <{p}AudienceRestriction><{p}Audience>https://signin.aws.amazon.com/saml</{p}Audience></{p}AudienceRestriction>
+    </{p}Conditions>
+    <{p}AttributeStatement>
+      <{p}Attribute Name="https://aws.amazon.com/SAML/Attributes/Role">
+        <{p}AttributeValue>arn:aws:iam::123456789012:role/Admin,arn:aws:iam::123456789012:saml-provider/AzureAD</{p}AttributeValue>
+        <{p}AttributeValue>arn:aws:iam::210987654321:saml-provider/AzureAD,arn:aws:iam::210987654321:role/ReadOnly</{p}AttributeValue>
+      </{p}Attribute>
+      <{p}Attribute Name="https://aws.amazon.com/SAML/Attributes/RoleSessionName">
+        <{p}AttributeValue>user@corp.com</{p}AttributeValue>
+      </{p}Attribute>
+      <{p}Attribute Name="https://aws.amazon.com/SAML/Attributes/SessionDuration">
+        <{p}AttributeValue>14400</{p}AttributeValue>
+      </{p}Attribute>
+      <{p}Attribute Name="https://aws.amazon.com/SAML/Attributes/PrincipalTag:Team">
+        <{p}AttributeValue>payments</{p}AttributeValue>
+      </{p}Attribute>
+    </{p}AttributeStatement>
+    <{p}AuthnStatement AuthnInstant="2023-09-14T03:12:40.000Z" SessionIndex="_session">
+      <{p}AuthnContext><{p}AuthnContextClassRef>urn:oasis:names:tc:SAML:2.0:ac:classes:Password</{p}AuthnContextClassRef></{p}AuthnContext>
+    </{p}AuthnStatement>
+  </{p}Assertion>
+</samlp:Response>"#
+        )
+    }
+
+    #[test]
+    fn namespace_prefixes_parse_the_same() {
+        for prefix in ["", "saml", "saml2"] {
+            let response = SamlResponse::from_xml(&response_xml(prefix)).unwrap();
+            let assertion = &response.assertion;
+
+            assert_eq!(response.id.as_deref(), Some("_response"), "{}", prefix);
+            assert_eq!(
+                response.status_code.as_deref(),
+                Some("urn:oasis:names:tc:SAML:2.0:status:Success")
+            );
+            assert_eq!(assertion.id.as_deref(), Some("_assertion"));
+            assert_eq!(assertion.issuer, "https://sts.windows.net/tenant/");
+            assert_eq!(assertion.subject.as_ref().unwrap().name_id, "user@corp.com");
+            assert_eq!(
+                assertion.conditions.as_ref().unwrap().audiences,
+                vec!["https://signin.aws.amazon.com/saml"]
+            );
+
+            let roles = assertion.roles().unwrap();
+            assert_eq!(roles.len(), 2, "{}", prefix);
+            assert_eq!(roles[0].role_arn, "arn:aws:iam::123456789012:role/Admin");
+            assert_eq!(
+                roles[1].principal_arn,
+                "arn:aws:iam::210987654321:saml-provider/AzureAD"
+            );
+
+            assert_eq!(assertion.attributes.len(), 4, "{}", prefix);
+            assert_eq!(assertion.role_session_name(), Some("user@corp.com"));
+            assert_eq!(assertion.session_duration(), Some(14400));
+            assert_eq!(
+                assertion.principal_tags().get("Team").map(|t| t.as_str()),
+                Some("payments")
+            );
+            assert_eq!(
+                assertion
+                    .authn_statement
+                    .as_ref()
+                    .unwrap()
+                    .authn_context_class_ref
+                    .as_deref(),
+                Some("urn:oasis:names:tc:SAML:2.0:ac:classes:Password")
+            );
         }
     }
 
-    Ok(roles)
+    #[test]
+    fn missing_assertion_is_an_error() {
+        let xml = r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" ID="_response"><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Responder"/></samlp:Status></samlp:Response>"#;
+
+        assert!(SamlResponse::from_xml(xml).is_err());
+    }
 }
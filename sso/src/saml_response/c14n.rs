@@ -0,0 +1,267 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use xml::name::OwnedName;
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+
+pub const EXCLUSIVE_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+pub const EXCLUSIVE_C14N_WITH_COMMENTS: &str =
+    "http://www.w3.org/2001/10/xml-exc-c14n#WithComments";
+const XMLDSIG_NAMESPACE: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+/// The part of the document to canonicalize
+pub enum Target<'a> {
+    /// The element with this `ID`, with its enveloped `Signature` removed
+    Element(&'a str),
+    /// The `SignedInfo` of the `Signature` enveloped in the element with this `ID`
+    SignedInfo(&'a str),
+}
+
+/// Exclusive XML canonicalization (without comments) of part of a document
+pub fn canonicalize(xml: &str, target: Target, inclusive_prefixes: &[String]) -> Result<String> {
+    let config = ParserConfig::new()
+        .trim_whitespace(false)
+        .whitespace_to_characters(true)
+        .cdata_to_characters(true)
+        .coalesce_characters(true)
+        .ignore_comments(true);
+    let reader = EventReader::new_with_config(xml.as_bytes(), config);
+
+    let mut output = String::new();
+    let mut found = false;
+
+    // IDs of the currently open elements, used to find the element enveloping a `Signature`
+    let mut ancestor_ids: Vec<Option<String>> = Vec::new();
+    // Namespace declarations rendered by each open element inside the target
+    let mut rendered: Vec<BTreeMap<String, String>> = Vec::new();
+    let mut skip_depth: Option<usize> = None;
+
+    for event in reader {
+        match event? {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let depth = ancestor_ids.len();
+                let id = attributes
+                    .iter()
+                    .find(|attr| attr.name.local_name == "ID" && attr.name.namespace.is_none())
+                    .map(|attr| attr.value.clone());
+                let parent_id = ancestor_ids.last().cloned().flatten();
+                let grandparent_id = depth
+                    .checked_sub(2)
+                    .and_then(|index| ancestor_ids[index].clone());
+                ancestor_ids.push(id.clone());
+
+                // IDs are unique, a repeat anywhere (even inside the target) could point elsewhere
+                if let Target::Element(target_id) = target {
+                    if found && id.as_deref() == Some(target_id) {
+                        bail!("Multiple elements match the signature reference");
+                    }
+                }
+
+                if skip_depth.is_some() {
+                    continue;
+                }
+
+                let in_dsig = name.namespace.as_deref() == Some(XMLDSIG_NAMESPACE);
+
+                if rendered.is_empty() {
+                    let is_target = match target {
+                        Target::Element(target_id) => id.as_deref() == Some(target_id),
+                        Target::SignedInfo(target_id) => {
+                            in_dsig
+                                && name.local_name == "SignedInfo"
+                                && grandparent_id.as_deref() == Some(target_id)
+                        }
+                    };
+
+                    if !is_target {
+                        continue;
+                    }
+
+                    if found {
+                        bail!("Multiple elements match the signature reference");
+                    }
+                    found = true;
+                } else if let Target::Element(target_id) = target {
+                    if in_dsig
+                        && name.local_name == "Signature"
+                        && parent_id.as_deref() == Some(target_id)
+                    {
+                        skip_depth = Some(depth);
+                        continue;
+                    }
+                }
+
+                let mut needed_prefixes: Vec<String> =
+                    vec![name.prefix.clone().unwrap_or_default()];
+                for attr in &attributes {
+                    if let Some(prefix) = &attr.name.prefix {
+                        if prefix != "xml" {
+                            needed_prefixes.push(prefix.clone());
+                        }
+                    }
+                }
+                for prefix in inclusive_prefixes {
+                    if prefix == "#default" {
+                        needed_prefixes.push(String::new());
+                    } else if namespace.get(prefix).is_some() {
+                        needed_prefixes.push(prefix.clone());
+                    }
+                }
+
+                let parent_rendered = rendered.last().cloned().unwrap_or_default();
+                let mut element_rendered = parent_rendered.clone();
+                let mut declarations = BTreeMap::new();
+                for prefix in needed_prefixes {
+                    let uri = namespace.get(&prefix).unwrap_or_default().to_string();
+                    let already_rendered = parent_rendered.get(&prefix).map(|u| u.as_str());
+                    let unchanged = match already_rendered {
+                        Some(rendered_uri) => rendered_uri == uri,
+                        None => prefix.is_empty() && uri.is_empty(),
+                    };
+                    if !unchanged {
+                        declarations.insert(prefix.clone(), uri.clone());
+                    }
+                    element_rendered.insert(prefix, uri);
+                }
+                rendered.push(element_rendered);
+
+                output.push('<');
+                output.push_str(&qualified_name(&name));
+
+                for (prefix, uri) in declarations {
+                    if prefix.is_empty() {
+                        output.push_str(&format!(r#" xmlns="{}""#, escape_attribute(&uri)));
+                    } else {
+                        output.push_str(&format!(
+                            r#" xmlns:{}="{}""#,
+                            prefix,
+                            escape_attribute(&uri)
+                        ));
+                    }
+                }
+
+                let mut sorted_attributes: Vec<_> = attributes.iter().collect();
+                sorted_attributes.sort_by(|a, b| {
+                    (
+                        a.name.namespace.as_deref().unwrap_or(""),
+                        &a.name.local_name,
+                    )
+                        .cmp(&(
+                            b.name.namespace.as_deref().unwrap_or(""),
+                            &b.name.local_name,
+                        ))
+                });
+                for attr in sorted_attributes {
+                    output.push_str(&format!(
+                        r#" {}="{}""#,
+                        qualified_name(&attr.name),
+                        escape_attribute(&attr.value)
+                    ));
+                }
+
+                output.push('>');
+            }
+            XmlEvent::EndElement { name } => {
+                let _ = ancestor_ids.pop();
+                let depth = ancestor_ids.len();
+
+                if skip_depth == Some(depth) {
+                    skip_depth = None;
+                    continue;
+                }
+                if skip_depth.is_some() || rendered.is_empty() {
+                    continue;
+                }
+
+                let _ = rendered.pop();
+                output.push_str(&format!("</{}>", qualified_name(&name)));
+            }
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text) | XmlEvent::CData(text)
+                if skip_depth.is_none() && !rendered.is_empty() =>
+            {
+                output.push_str(&escape_text(&text));
+            }
+            _ => {}
+        }
+    }
+
+    if !found {
+        bail!("No element matches the signature reference");
+    }
+
+    Ok(output)
+}
+
+fn qualified_name(name: &OwnedName) -> String {
+    match &name.prefix {
+        Some(prefix) => format!("{}:{}", prefix, name.local_name),
+        None => name.local_name.clone(),
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<r:Root xmlns:r="urn:root" xmlns:unused="urn:unused"><a:Item xmlns:a="urn:a" xmlns="urn:default" z="1" a:b="2" ID="_x">text &amp; &lt;more&gt; "quoted"<Child attr="a&quot;b"/><!-- comment --><a:Empty/></a:Item></r:Root>"#;
+
+    #[test]
+    fn canonicalizes_element() {
+        // Matches `xmllint --exc-c14n` without the comment
+        assert_eq!(
+            canonicalize(XML, Target::Element("_x"), &[]).unwrap(),
+            r#"<a:Item xmlns:a="urn:a" ID="_x" z="1" a:b="2">text &amp; &lt;more&gt; "quoted"<Child xmlns="urn:default" attr="a&quot;b"></Child><a:Empty></a:Empty></a:Item>"#
+        );
+    }
+
+    #[test]
+    fn renders_inclusive_prefixes() {
+        let canonical = canonicalize(XML, Target::Element("_x"), &["unused".to_string()]).unwrap();
+
+        assert!(
+            canonical.starts_with(r#"<a:Item xmlns:a="urn:a" xmlns:unused="urn:unused" ID="_x""#)
+        );
+    }
+
+    #[test]
+    fn removes_enveloped_signature() {
+        let xml = r##"<Assertion ID="_a"><Issuer>x</Issuer><ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo><ds:Reference URI="#_a"/></ds:SignedInfo></ds:Signature><Subject/></Assertion>"##;
+
+        assert_eq!(
+            canonicalize(xml, Target::Element("_a"), &[]).unwrap(),
+            r#"<Assertion ID="_a"><Issuer>x</Issuer><Subject></Subject></Assertion>"#
+        );
+        assert_eq!(
+            canonicalize(xml, Target::SignedInfo("_a"), &[]).unwrap(),
+            r##"<ds:SignedInfo xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:Reference URI="#_a"></ds:Reference></ds:SignedInfo>"##
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_duplicate_ids() {
+        assert!(canonicalize(XML, Target::Element("_missing"), &[]).is_err());
+
+        let duplicated = XML.replace("<a:Empty/>", r#"<a:Empty ID="_x"/>"#);
+        assert!(canonicalize(&duplicated, Target::Element("_x"), &[]).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use crate::helpers::base64_decode;
+use crate::saml_response::{EncryptedAssertionXml, EncryptedKeyXml, ResponseXml};
+use aes::cipher::block_padding::NoPadding;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes128Gcm, Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Result};
+use file_manager::aws_config::AwsConfig;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{Oaep, Pkcs1v15Encrypt, RsaPrivateKey};
+use serde_xml_rs::from_str;
+use sha1::Sha1;
+use sha2::Sha256;
+use std::path::Path;
+
+const RSA_OAEP_MGF1P: &str = "http://www.w3.org/2001/04/xmlenc#rsa-oaep-mgf1p";
+const RSA_OAEP: &str = "http://www.w3.org/2009/xmlenc11#rsa-oaep";
+const RSA_1_5: &str = "http://www.w3.org/2001/04/xmlenc#rsa-1_5";
+const MGF1_SHA256: &str = "http://www.w3.org/2009/xmlenc11#mgf1sha256";
+const DIGEST_SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const AES128_CBC: &str = "http://www.w3.org/2001/04/xmlenc#aes128-cbc";
+const AES256_CBC: &str = "http://www.w3.org/2001/04/xmlenc#aes256-cbc";
+const AES128_GCM: &str = "http://www.w3.org/2009/xmlenc11#aes128-gcm";
+const AES256_GCM: &str = "http://www.w3.org/2009/xmlenc11#aes256-gcm";
+const BLOCK_SIZE: usize = 16;
+
+#[derive(Clone)]
+pub struct DecryptionKey {
+    private_key: RsaPrivateKey,
+}
+
+impl DecryptionKey {
+    /// Reads a PKCS#8 or PKCS#1 PEM private key
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        let private_key = RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .map_err(|e| anyhow!("Unable to read the SAML decryption key: {}", e))?;
+
+        Ok(Self { private_key })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let pem = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))?;
+        Self::from_pem(&pem)
+    }
+
+    pub fn from_config(config: &AwsConfig) -> Result<Option<Self>> {
+        config
+            .azure_saml_decryption_key
+            .as_ref()
+            .map(|path| Self::load(Path::new(path)))
+            .transpose()
+    }
+
+    fn unwrap_key(&self, encrypted_key: &EncryptedKeyXml) -> Result<Vec<u8>> {
+        let method = &encrypted_key.encryption_method;
+        let cipher_value = base64_decode(&encrypted_key.cipher_data.cipher_value)?;
+
+        let sha256_digest = method
+            .digest_method
+            .as_ref()
+            .is_some_and(|digest| digest.algorithm == DIGEST_SHA256);
+        let sha256_mgf = method
+            .mgf
+            .as_ref()
+            .is_some_and(|mgf| mgf.algorithm == MGF1_SHA256);
+
+        let key = match method.algorithm.as_str() {
+            RSA_OAEP_MGF1P | RSA_OAEP => match (sha256_digest, sha256_mgf) {
+                (false, false) => self.private_key.decrypt(Oaep::new::<Sha1>(), &cipher_value),
+                (true, false) => self
+                    .private_key
+                    .decrypt(Oaep::new_with_mgf_hash::<Sha256, Sha1>(), &cipher_value),
+                (false, true) => self
+                    .private_key
+                    .decrypt(Oaep::new_with_mgf_hash::<Sha1, Sha256>(), &cipher_value),
+                (true, true) => self
+                    .private_key
+                    .decrypt(Oaep::new::<Sha256>(), &cipher_value),
+            },
+            RSA_1_5 => self.private_key.decrypt(Pkcs1v15Encrypt, &cipher_value),
+            algorithm => bail!("Unsupported key transport algorithm {}", algorithm),
+        };
+
+        key.map_err(|_| {
+            anyhow!("Unable to decrypt the SAML assertion key, check azure_saml_decryption_key matches the app's token encryption certificate")
+        })
+    }
+}
+
+/// Decrypts the `EncryptedAssertion` of a decoded SAML response, returning the `Assertion` XML
+pub fn decrypt_assertion(xml: &str, key: &DecryptionKey) -> Result<String> {
+    let response: ResponseXml = from_str(xml)?;
+    let encrypted_assertion = response
+        .encrypted_assertion
+        .ok_or(anyhow!("No EncryptedAssertion found in the SAML response"))?;
+
+    decrypt(&encrypted_assertion, key)
+}
+
+pub(super) fn decrypt(
+    encrypted_assertion: &EncryptedAssertionXml,
+    key: &DecryptionKey,
+) -> Result<String> {
+    let encrypted_data = &encrypted_assertion.encrypted_data;
+
+    // The key is either inside the data's KeyInfo or a sibling of the data
+    let encrypted_key = encrypted_data
+        .key_info
+        .as_ref()
+        .and_then(|key_info| key_info.encrypted_key.as_ref())
+        .or(encrypted_assertion.encrypted_key.first())
+        .ok_or(anyhow!("No EncryptedKey found in the EncryptedAssertion"))?;
+
+    let content_key = key.unwrap_key(encrypted_key)?;
+    let cipher_value = base64_decode(&encrypted_data.cipher_data.cipher_value)?;
+
+    let plaintext = match encrypted_data.encryption_method.algorithm.as_str() {
+        AES128_CBC => decrypt_cbc::<aes::Aes128>(&content_key, &cipher_value)?,
+        AES256_CBC => decrypt_cbc::<aes::Aes256>(&content_key, &cipher_value)?,
+        AES128_GCM => decrypt_gcm::<Aes128Gcm>(&content_key, &cipher_value)?,
+        AES256_GCM => decrypt_gcm::<Aes256Gcm>(&content_key, &cipher_value)?,
+        algorithm => bail!("Unsupported content encryption algorithm {}", algorithm),
+    };
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
+// `usize::is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn decrypt_cbc<C>(key: &[u8], cipher_value: &[u8]) -> Result<Vec<u8>>
+where
+    C: aes::cipher::BlockCipher + aes::cipher::BlockDecrypt + aes::cipher::KeyInit,
+{
+    if cipher_value.len() < 2 * BLOCK_SIZE || cipher_value.len() % BLOCK_SIZE != 0 {
+        bail!("Malformed AES-CBC cipher data");
+    }
+
+    let (iv, ciphertext) = cipher_value.split_at(BLOCK_SIZE);
+    let mut plaintext = cbc::Decryptor::<C>::new_from_slices(key, iv)
+        .map_err(|_| anyhow!("Invalid AES-CBC key length"))?
+        .decrypt_padded_vec_mut::<NoPadding>(ciphertext)
+        .map_err(|_| anyhow!("Unable to decrypt the SAML assertion"))?;
+
+    // XML Encryption uses ISO 10126 padding, only the last byte is meaningful
+    let padding = *plaintext.last().unwrap() as usize;
+    if padding == 0 || padding > BLOCK_SIZE || padding > plaintext.len() {
+        bail!("Unable to decrypt the SAML assertion, invalid padding");
+    }
+    plaintext.truncate(plaintext.len() - padding);
+
+    Ok(plaintext)
+}
+
+fn decrypt_gcm<C>(key: &[u8], cipher_value: &[u8]) -> Result<Vec<u8>>
+where
+    C: KeyInit + Aead<NonceSize = aes_gcm::aead::consts::U12>,
+{
+    if cipher_value.len() < 12 + 16 {
+        bail!("Malformed AES-GCM cipher data");
+    }
+
+    let (iv, ciphertext) = cipher_value.split_at(12);
+    C::new_from_slice(key)
+        .map_err(|_| anyhow!("Invalid AES-GCM key length"))?
+        .decrypt(Nonce::from_slice(iv), ciphertext)
+        .map_err(|_| anyhow!("Unable to decrypt the SAML assertion"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saml_response::SamlResponse;
+    use aes::cipher::BlockEncryptMut;
+    use base64::engine::general_purpose;
+    use base64::Engine;
+    use rand::RngCore;
+    use rsa::RsaPublicKey;
+
+    const DECRYPTION_KEY: &str = include_str!("../../tests/fixtures/decryption_key.pem");
+    const OTHER_DECRYPTION_KEY: &str =
+        include_str!("../../tests/fixtures/other_decryption_key.pem");
+    const ASSERTION: &str = r#"<Assertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion" ID="_assertion" Version="2.0"><Issuer>https://sts.windows.net/tenant/</Issuer><AttributeStatement><Attribute Name="https://aws.amazon.com/SAML/Attributes/Role"><AttributeValue>arn:aws:iam::123456789012:role/Admin,arn:aws:iam::123456789012:saml-provider/AzureAD</AttributeValue></Attribute></AttributeStatement></Assertion>"#;
+
+    fn base64(bytes: &[u8]) -> String {
+        general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn random_bytes(length: usize) -> Vec<u8> {
+        let mut bytes = vec![0; length];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        bytes
+    }
+
+    /// Encrypts with AES-256-CBC and ISO 10126 padding, `padding` overrides the final padding byte
+    fn encrypt_cbc(key: &[u8], plaintext: &[u8], padding: Option<u8>) -> Vec<u8> {
+        let mut padded = plaintext.to_vec();
+        let length = BLOCK_SIZE - plaintext.len() % BLOCK_SIZE;
+        padded.extend(random_bytes(length - 1));
+        padded.push(padding.unwrap_or(length as u8));
+
+        let iv = random_bytes(16);
+        let ciphertext = cbc::Encryptor::<aes::Aes256>::new_from_slices(key, &iv)
+            .unwrap()
+            .encrypt_padded_vec_mut::<NoPadding>(&padded);
+        [iv, ciphertext].concat()
+    }
+
+    fn encrypt_gcm(key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let iv = random_bytes(12);
+        let ciphertext = Aes256Gcm::new_from_slice(key)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&iv), plaintext)
+            .unwrap();
+        [iv, ciphertext].concat()
+    }
+
+    fn encrypted_key(public_key: &RsaPublicKey, algorithm: &str, content_key: &[u8]) -> String {
+        let mut rng = rand::thread_rng();
+        let (wrapped, digest) = match algorithm {
+            RSA_1_5 => (
+                public_key.encrypt(&mut rng, Pkcs1v15Encrypt, content_key),
+                String::new(),
+            ),
+            RSA_OAEP => (
+                public_key.encrypt(&mut rng, Oaep::new::<Sha256>(), content_key),
+                format!(
+                    r#"<DigestMethod xmlns="http://www.w3.org/2000/09/xmldsig#" Algorithm="{}"/><xenc11:MGF xmlns:xenc11="http://www.w3.org/2009/xmlenc11#" Algorithm="{}"/>"#,
+                    DIGEST_SHA256, MGF1_SHA256
+                ),
+            ),
+            _ => (
+                public_key.encrypt(&mut rng, Oaep::new::<Sha1>(), content_key),
+                String::new(),
+            ),
+        };
+
+        format!(
+            r#"<xenc:EncryptedKey xmlns:xenc="http://www.w3.org/2001/04/xmlenc#"><xenc:EncryptionMethod Algorithm="{}">{}</xenc:EncryptionMethod><xenc:CipherData><xenc:CipherValue>{}</xenc:CipherValue></xenc:CipherData></xenc:EncryptedKey>"#,
+            algorithm,
+            digest,
+            base64(&wrapped.unwrap())
+        )
+    }
+
+    /// A response with the key inside the data's `KeyInfo`, or next to the data when `sibling_key`
+    fn encrypted_response(
+        content_algorithm: &str,
+        cipher_value: &[u8],
+        encrypted_key: &str,
+        sibling_key: bool,
+    ) -> String {
+        let (key_info, sibling) = if sibling_key {
+            (String::new(), encrypted_key.to_string())
+        } else {
+            (
+                format!(
+                    r#"<KeyInfo xmlns="http://www.w3.org/2000/09/xmldsig#">{}</KeyInfo>"#,
+                    encrypted_key
+                ),
+                String::new(),
+            )
+        };
+
+        format!(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" ID="_response"><EncryptedAssertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><xenc:EncryptedData xmlns:xenc="http://www.w3.org/2001/04/xmlenc#" Type="http://www.w3.org/2001/04/xmlenc#Element"><xenc:EncryptionMethod Algorithm="{}"/>{}<xenc:CipherData><xenc:CipherValue>{}</xenc:CipherValue></xenc:CipherData></xenc:EncryptedData>{}</EncryptedAssertion></samlp:Response>"#,
+            content_algorithm,
+            key_info,
+            base64(cipher_value),
+            sibling
+        )
+    }
+
+    fn decryption_key() -> DecryptionKey {
+        DecryptionKey::from_pem(DECRYPTION_KEY).unwrap()
+    }
+
+    fn public_key() -> RsaPublicKey {
+        RsaPublicKey::from(&decryption_key().private_key)
+    }
+
+    #[test]
+    fn round_trips_cbc_with_oaep() {
+        let content_key = random_bytes(32);
+        let cipher_value = encrypt_cbc(&content_key, ASSERTION.as_bytes(), None);
+        let encrypted_key = encrypted_key(&public_key(), RSA_OAEP_MGF1P, &content_key);
+        let xml = encrypted_response(AES256_CBC, &cipher_value, &encrypted_key, false);
+
+        assert_eq!(
+            decrypt_assertion(&xml, &decryption_key()).unwrap(),
+            ASSERTION
+        );
+
+        let saml_response = SamlResponse::from_xml_with_key(&xml, Some(&decryption_key())).unwrap();
+        assert_eq!(
+            saml_response.assertion.roles().unwrap()[0].role_arn,
+            "arn:aws:iam::123456789012:role/Admin"
+        );
+    }
+
+    #[test]
+    fn round_trips_gcm_with_sha256_oaep() {
+        let content_key = random_bytes(32);
+        let cipher_value = encrypt_gcm(&content_key, ASSERTION.as_bytes());
+        let encrypted_key = encrypted_key(&public_key(), RSA_OAEP, &content_key);
+        let xml = encrypted_response(AES256_GCM, &cipher_value, &encrypted_key, true);
+
+        assert_eq!(
+            decrypt_assertion(&xml, &decryption_key()).unwrap(),
+            ASSERTION
+        );
+    }
+
+    #[test]
+    fn round_trips_pkcs1_key_transport() {
+        let content_key = random_bytes(32);
+        let cipher_value = encrypt_cbc(&content_key, ASSERTION.as_bytes(), None);
+        let encrypted_key = encrypted_key(&public_key(), RSA_1_5, &content_key);
+        let xml = encrypted_response(AES256_CBC, &cipher_value, &encrypted_key, false);
+
+        assert_eq!(
+            decrypt_assertion(&xml, &decryption_key()).unwrap(),
+            ASSERTION
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let content_key = random_bytes(32);
+        let cipher_value = encrypt_gcm(&content_key, ASSERTION.as_bytes());
+        let encrypted_key = encrypted_key(&public_key(), RSA_OAEP_MGF1P, &content_key);
+        let xml = encrypted_response(AES256_GCM, &cipher_value, &encrypted_key, true);
+        let other_key = DecryptionKey::from_pem(OTHER_DECRYPTION_KEY).unwrap();
+
+        let message = decrypt_assertion(&xml, &other_key).unwrap_err().to_string();
+        assert!(message.contains("Unable to decrypt the SAML assertion key"));
+    }
+
+    #[test]
+    fn rejects_bad_padding() {
+        let content_key = random_bytes(32);
+        let encrypted_key = encrypted_key(&public_key(), RSA_OAEP_MGF1P, &content_key);
+
+        for padding in [0, 17] {
+            let cipher_value = encrypt_cbc(&content_key, ASSERTION.as_bytes(), Some(padding));
+            let xml = encrypted_response(AES256_CBC, &cipher_value, &encrypted_key, false);
+
+            let message = decrypt_assertion(&xml, &decryption_key())
+                .unwrap_err()
+                .to_string();
+            assert!(message.contains("invalid padding"), "{}", padding);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_cbc_data() {
+        let content_key = random_bytes(32);
+        let encrypted_key = encrypted_key(&public_key(), RSA_OAEP_MGF1P, &content_key);
+        let mut cipher_value = encrypt_cbc(&content_key, ASSERTION.as_bytes(), None);
+        cipher_value.pop();
+        let xml = encrypted_response(AES256_CBC, &cipher_value, &encrypted_key, false);
+
+        let message = decrypt_assertion(&xml, &decryption_key())
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Malformed AES-CBC cipher data"));
+    }
+
+    #[test]
+    fn rejects_tampered_gcm_data() {
+        let content_key = random_bytes(32);
+        let encrypted_key = encrypted_key(&public_key(), RSA_OAEP_MGF1P, &content_key);
+        let mut cipher_value = encrypt_gcm(&content_key, ASSERTION.as_bytes());
+        cipher_value[20] ^= 1;
+        let xml = encrypted_response(AES256_GCM, &cipher_value, &encrypted_key, true);
+
+        assert!(decrypt_assertion(&xml, &decryption_key()).is_err());
+    }
+}
@@ -0,0 +1,681 @@
+use crate::federation_metadata::FederationMetadata;
+use crate::helpers::base64_decode;
+use crate::saml_request::assertion_consumer_service_url;
+use crate::saml_response::c14n::{self, Target, EXCLUSIVE_C14N, EXCLUSIVE_C14N_WITH_COMMENTS};
+use crate::saml_response::decryption::{self, DecryptionKey};
+use crate::saml_response::{
+    AssertionXml, InclusiveNamespacesXml, ResponseXml, SamlResponse, SignatureXml,
+};
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use file_manager::aws_config::AwsConfig;
+use file_manager::clock_skew::ClockSkew;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use rsa::RsaPublicKey;
+use serde_xml_rs::from_str;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::Certificate;
+use xml::reader::{EventReader, XmlEvent};
+
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const RSA_SHA1: &str = "http://www.w3.org/2000/09/xmldsig#rsa-sha1";
+const DIGEST_SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const DIGEST_SHA1: &str = "http://www.w3.org/2000/09/xmldsig#sha1";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+
+#[derive(Clone, Debug)]
+pub struct SigningCertificate {
+    pub der: Vec<u8>,
+}
+
+impl SigningCertificate {
+    pub fn from_base64(base64: &str) -> Result<Self> {
+        let der = base64_decode(base64)?;
+        Certificate::from_der(&der)?;
+        Ok(Self { der })
+    }
+
+    /// Reads certificates from PEM, bare base64 or federation metadata XML
+    pub fn parse_all(text: &str) -> Result<Vec<Self>> {
+        let certificates = if text.contains("-----BEGIN CERTIFICATE-----") {
+            text.split("-----BEGIN CERTIFICATE-----")
+                .skip(1)
+                .filter_map(|block| block.split("-----END CERTIFICATE-----").next())
+                .map(Self::from_base64)
+                .collect::<Result<Vec<_>>>()?
+        } else if text
+            .trim_start_matches('\u{feff}')
+            .trim_start()
+            .starts_with('<')
+        {
+            Self::parse_xml(text.trim_start_matches('\u{feff}'))?
+        } else {
+            vec![Self::from_base64(text)?]
+        };
+
+        if certificates.is_empty() {
+            bail!("No certificates found");
+        }
+
+        Ok(certificates)
+    }
+
+    /// Federation metadata gives its signing keys, any other XML all of its `X509Certificate`s
+    fn parse_xml(xml: &str) -> Result<Vec<Self>> {
+        let mut certificates: Vec<String> = Vec::new();
+        let mut in_certificate = false;
+        let mut is_root = true;
+
+        for event in EventReader::new(xml.as_bytes()) {
+            match event? {
+                XmlEvent::StartElement { name, .. } => {
+                    if is_root && name.local_name == "EntityDescriptor" {
+                        return Ok(FederationMetadata::from_xml(xml)?.signing_certificates);
+                    }
+                    is_root = false;
+
+                    in_certificate = name.local_name == "X509Certificate";
+                    if in_certificate {
+                        certificates.push(String::new());
+                    }
+                }
+                XmlEvent::Characters(text) if in_certificate => {
+                    if let Some(certificate) = certificates.last_mut() {
+                        certificate.push_str(&text);
+                    }
+                }
+                XmlEvent::EndElement { .. } => in_certificate = false,
+                _ => {}
+            }
+        }
+
+        certificates
+            .iter()
+            .map(|certificate| Self::from_base64(certificate))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))?;
+        Self::parse_all(&text)
+    }
+
+    /// SHA-1 thumbprint, as shown in the Azure portal
+    pub fn thumbprint(&self) -> String {
+        Sha1::digest(&self.der)
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect()
+    }
+
+    pub fn to_pem(&self) -> String {
+        let base64 = general_purpose::STANDARD.encode(&self.der);
+        let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+        for line in base64.as_bytes().chunks(64) {
+            pem.push_str(std::str::from_utf8(line).unwrap());
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+        pem
+    }
+
+    pub fn not_after(&self) -> Result<DateTime<Utc>> {
+        let certificate = Certificate::from_der(&self.der)?;
+        let not_after = certificate
+            .tbs_certificate
+            .validity
+            .not_after
+            .to_unix_duration();
+        DateTime::from_timestamp(not_after.as_secs() as i64, 0)
+            .ok_or(anyhow!("Invalid certificate expiry"))
+    }
+
+    fn public_key(&self) -> Result<RsaPublicKey> {
+        let certificate = Certificate::from_der(&self.der)?;
+        let spki_der = certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()?;
+        Ok(RsaPublicKey::from_public_key_der(&spki_der)?)
+    }
+}
+
+pub struct VerificationOptions {
+    pub certificates: Vec<SigningCertificate>,
+    pub audience: Option<String>,
+    pub issuer: Option<String>,
+    pub destination: Option<String>,
+    pub decryption_key: Option<DecryptionKey>,
+    pub now: DateTime<Utc>,
+    pub allowed_clock_skew: Duration,
+}
+
+impl VerificationOptions {
+    pub fn from_config(config: &AwsConfig) -> Result<Self> {
+        let certificate_path = config.azure_signing_certificate.as_ref().ok_or(anyhow!(
+            "azure_signing_certificate must be set to verify SAML signatures"
+        ))?;
+
+        Ok(Self {
+            certificates: SigningCertificate::load(Path::new(certificate_path))?,
+            audience: config.azure_app_id_uri.clone(),
+            issuer: config.azure_idp_entity_id.clone(),
+            destination: Some(assertion_consumer_service_url(config)),
+            decryption_key: DecryptionKey::from_config(config)?,
+            now: ClockSkew::now(),
+            allowed_clock_skew: Duration::minutes(3),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VerifiedSignature {
+    /// `Response` or `Assertion`
+    pub signed_element: String,
+    pub certificate_thumbprint: String,
+}
+
+/// Verifies the signature of a decoded SAML response, and the conditions of its assertion
+pub fn verify_saml_response(xml: &str, options: &VerificationOptions) -> Result<VerifiedSignature> {
+    // A second assertion next to the signed one is how signature wrapping sneaks in unsigned roles
+    if assertion_count(xml)? > 1 {
+        bail!("The SAML response contains more than one assertion");
+    }
+
+    let response: ResponseXml = from_str(xml)?;
+
+    let (signed_element, certificate) = if let Some(signature) = &response.signature {
+        let id = response
+            .id
+            .as_deref()
+            .ok_or(anyhow!("The signed Response has no ID"))?;
+        let certificate = verify_signature(xml, id, signature, &options.certificates)?;
+        ("Response", certificate)
+    } else if let Some(assertion) = &response.assertion {
+        let signature = assertion
+            .signature
+            .as_ref()
+            .ok_or(anyhow!("The SAML response is not signed"))?;
+        let id = assertion
+            .id
+            .as_deref()
+            .ok_or(anyhow!("The signed Assertion has no ID"))?;
+        let certificate = verify_signature(xml, id, signature, &options.certificates)?;
+        ("Assertion", certificate)
+    } else if let Some(encrypted_assertion) = &response.encrypted_assertion {
+        let decryption_key = options.decryption_key.as_ref().ok_or(anyhow!(
+            "The SAML assertion is encrypted, set azure_saml_decryption_key to verify it"
+        ))?;
+        let assertion_xml = decryption::decrypt(encrypted_assertion, decryption_key)?;
+        let assertion: AssertionXml = from_str(&assertion_xml)?;
+        let signature = assertion
+            .signature
+            .as_ref()
+            .ok_or(anyhow!("The SAML response is not signed"))?;
+        let id = assertion
+            .id
+            .as_deref()
+            .ok_or(anyhow!("The signed Assertion has no ID"))?;
+        let certificate = verify_signature(&assertion_xml, id, signature, &options.certificates)?;
+        ("Assertion", certificate)
+    } else {
+        bail!("No Assertion found in the SAML response");
+    };
+
+    let saml_response = SamlResponse::from_xml_with_key(xml, options.decryption_key.as_ref())?;
+    verify_conditions(&saml_response, options)?;
+
+    Ok(VerifiedSignature {
+        signed_element: signed_element.to_string(),
+        certificate_thumbprint: certificate.thumbprint(),
+    })
+}
+
+pub fn verify_conditions(
+    saml_response: &SamlResponse,
+    options: &VerificationOptions,
+) -> Result<()> {
+    let assertion = &saml_response.assertion;
+    let skew = options.allowed_clock_skew;
+
+    if let Some(conditions) = &assertion.conditions {
+        if let Some(not_before) = conditions.not_before {
+            if options.now + skew < not_before {
+                bail!("The SAML assertion is not valid before {}", not_before);
+            }
+        }
+        if let Some(not_on_or_after) = conditions.not_on_or_after {
+            if options.now - skew >= not_on_or_after {
+                bail!("The SAML assertion expired at {}", not_on_or_after);
+            }
+        }
+    }
+
+    if let Some(audience) = &options.audience {
+        let audiences = assertion
+            .conditions
+            .as_ref()
+            .map(|conditions| conditions.audiences.as_slice())
+            .unwrap_or_default();
+        if audiences.is_empty() {
+            bail!(
+                "The SAML assertion has no audience restriction, expected {}",
+                audience
+            );
+        }
+        if !audiences.contains(audience) {
+            bail!(
+                "The SAML assertion is for audience {}, expected {}",
+                audiences.join(", "),
+                audience
+            );
+        }
+    }
+
+    if let Some(issuer) = &options.issuer {
+        if assertion.issuer != *issuer {
+            bail!(
+                "The SAML assertion was issued by {}, expected {}",
+                assertion.issuer,
+                issuer
+            );
+        }
+    }
+
+    if let Some(subject) = &assertion.subject {
+        if let Some(not_on_or_after) = subject.not_on_or_after {
+            if options.now - skew >= not_on_or_after {
+                bail!(
+                    "The SAML subject confirmation expired at {}",
+                    not_on_or_after
+                );
+            }
+        }
+        if let (Some(recipient), Some(destination)) = (&subject.recipient, &options.destination) {
+            if recipient != destination {
+                bail!(
+                    "The SAML assertion is for recipient {}, expected {}",
+                    recipient,
+                    destination
+                );
+            }
+        }
+    }
+
+    if let (Some(actual), Some(expected)) = (&saml_response.destination, &options.destination) {
+        if actual != expected {
+            bail!(
+                "The SAML response is for destination {}, expected {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_signature<'a>(
+    xml: &str,
+    id: &str,
+    signature: &SignatureXml,
+    certificates: &'a [SigningCertificate],
+) -> Result<&'a SigningCertificate> {
+    let signed_info = &signature.signed_info;
+    let reference = &signed_info.reference;
+
+    if reference.uri.trim_start_matches('#') != id {
+        bail!(
+            "The signature references {}, not the signed element {}",
+            reference.uri,
+            id
+        );
+    }
+
+    // Comments are always dropped by canonicalize, so a WithComments digest could never match
+    match signed_info.canonicalization_method.algorithm.as_str() {
+        EXCLUSIVE_C14N => {}
+        EXCLUSIVE_C14N_WITH_COMMENTS => bail!(
+            "Canonicalization with comments ({}) is not supported, only {}",
+            EXCLUSIVE_C14N_WITH_COMMENTS,
+            EXCLUSIVE_C14N
+        ),
+        algorithm => bail!("Unsupported canonicalization method {}", algorithm),
+    }
+
+    let mut inclusive_prefixes = Vec::new();
+    for transform in &reference.transforms.transform {
+        match transform.algorithm.as_str() {
+            ENVELOPED_SIGNATURE => {}
+            EXCLUSIVE_C14N => {
+                inclusive_prefixes = prefix_list(&transform.inclusive_namespaces);
+            }
+            EXCLUSIVE_C14N_WITH_COMMENTS => bail!(
+                "Canonicalization with comments ({}) is not supported, only {}",
+                EXCLUSIVE_C14N_WITH_COMMENTS,
+                EXCLUSIVE_C14N
+            ),
+            algorithm => bail!("Unsupported signature transform {}", algorithm),
+        }
+    }
+
+    let canonical_element = c14n::canonicalize(xml, Target::Element(id), &inclusive_prefixes)?;
+    let digest = match reference.digest_method.algorithm.as_str() {
+        DIGEST_SHA256 => Sha256::digest(canonical_element.as_bytes()).to_vec(),
+        DIGEST_SHA1 => Sha1::digest(canonical_element.as_bytes()).to_vec(),
+        algorithm => bail!("Unsupported digest method {}", algorithm),
+    };
+    let expected_digest = base64_decode(&reference.digest_value)?;
+    if digest != expected_digest {
+        bail!("The SAML digest does not match, the response has been modified after signing");
+    }
+
+    let canonical_signed_info = c14n::canonicalize(
+        xml,
+        Target::SignedInfo(id),
+        &prefix_list(&signed_info.canonicalization_method.inclusive_namespaces),
+    )?;
+    let signature_value =
+        Signature::try_from(base64_decode(&signature.signature_value)?.as_slice())?;
+    let signature_method = signed_info.signature_method.algorithm.as_str();
+
+    if certificates.is_empty() {
+        bail!("No signing certificates configured");
+    }
+
+    for certificate in certificates {
+        let public_key = certificate.public_key()?;
+        let verified = match signature_method {
+            RSA_SHA256 => VerifyingKey::<Sha256>::new(public_key)
+                .verify(canonical_signed_info.as_bytes(), &signature_value)
+                .is_ok(),
+            RSA_SHA1 => VerifyingKey::<Sha1>::new(public_key)
+                .verify(canonical_signed_info.as_bytes(), &signature_value)
+                .is_ok(),
+            algorithm => bail!("Unsupported signature method {}", algorithm),
+        };
+
+        if verified {
+            return Ok(certificate);
+        }
+    }
+
+    let stored_thumbprints = certificates
+        .iter()
+        .map(|certificate| certificate.thumbprint())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match embedded_certificate(signature) {
+        Some(embedded) if !certificates.iter().any(|c| c.der == embedded.der) => bail!(
+            "The SAML response is signed by certificate {} but the stored certificate is {}, \
+            the tenant may have rotated its signing certificate, re-import the federation metadata",
+            embedded.thumbprint(),
+            stored_thumbprints
+        ),
+        _ => bail!(
+            "The SAML signature is not valid for certificate {}",
+            stored_thumbprints
+        ),
+    }
+}
+
+/// Assertions, encrypted or not, directly inside the `Response`
+fn assertion_count(xml: &str) -> Result<usize> {
+    let mut depth = 0;
+    let mut count = 0;
+
+    for event in EventReader::new(xml.as_bytes()) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                if depth == 1
+                    && (name.local_name == "Assertion" || name.local_name == "EncryptedAssertion")
+                {
+                    count += 1;
+                }
+                depth += 1;
+            }
+            XmlEvent::EndElement { .. } => depth -= 1,
+            _ => {}
+        }
+    }
+
+    Ok(count)
+}
+
+fn embedded_certificate(signature: &SignatureXml) -> Option<SigningCertificate> {
+    let certificate = signature
+        .key_info
+        .as_ref()?
+        .x509_data
+        .as_ref()?
+        .x509_certificate
+        .as_ref()?;
+    SigningCertificate::from_base64(certificate).ok()
+}
+
+fn prefix_list(inclusive_namespaces: &Option<InclusiveNamespacesXml>) -> Vec<String> {
+    inclusive_namespaces
+        .as_ref()
+        .and_then(|namespaces| namespaces.prefix_list.as_ref())
+        .map(|list| list.split_whitespace().map(|p| p.to_string()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNED_RESPONSE: &str = include_str!("../../tests/fixtures/signed_response.xml");
+    const SIGNED_RESPONSE_SAML2: &str =
+        include_str!("../../tests/fixtures/signed_response_saml2.xml");
+    const SIGNING_CERTIFICATE: &str = include_str!("../../tests/fixtures/signing_certificate.pem");
+    const ROTATED_CERTIFICATE: &str = include_str!("../../tests/fixtures/rotated_certificate.pem");
+    const AWS_SIGNIN: &str = "https://signin.aws.amazon.com/saml";
+
+    fn instant(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn options(certificate: &str) -> VerificationOptions {
+        VerificationOptions {
+            certificates: SigningCertificate::parse_all(certificate).unwrap(),
+            audience: Some(AWS_SIGNIN.to_string()),
+            issuer: Some("https://sts.windows.net/tenant-1/".to_string()),
+            destination: Some(AWS_SIGNIN.to_string()),
+            decryption_key: None,
+            now: instant("2026-10-18T20:50:30Z"),
+            allowed_clock_skew: Duration::minutes(3),
+        }
+    }
+
+    fn error(xml: &str, options: &VerificationOptions) -> String {
+        verify_saml_response(xml, options).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn verifies_signed_assertion() {
+        let options = options(SIGNING_CERTIFICATE);
+
+        for xml in [SIGNED_RESPONSE, SIGNED_RESPONSE_SAML2] {
+            let verified = verify_saml_response(xml, &options).unwrap();
+
+            assert_eq!(verified.signed_element, "Assertion");
+            assert_eq!(
+                verified.certificate_thumbprint,
+                options.certificates[0].thumbprint()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_digest() {
+        let tampered = SIGNED_RESPONSE.replace("payments &amp; billing", "payments &amp; admins");
+
+        assert!(error(&tampered, &options(SIGNING_CERTIFICATE)).contains("digest does not match"));
+    }
+
+    #[test]
+    fn rejects_tampered_signed_info() {
+        // Updating the digest to match the tampered assertion changes SignedInfo, breaking its signature
+        let tampered = SIGNED_RESPONSE.replace("role/Admin", "role/Owner");
+        let canonical = c14n::canonicalize(&tampered, Target::Element("_assert1"), &[]).unwrap();
+        let digest = general_purpose::STANDARD.encode(Sha256::digest(canonical.as_bytes()));
+        let tampered = tampered.replace("4J+kczeQIza8TYNeTGsZtd3QxurEvvq7BsDuiVD2c8A=", &digest);
+
+        assert!(error(&tampered, &options(SIGNING_CERTIFICATE))
+            .contains("signature is not valid for certificate"));
+    }
+
+    #[test]
+    fn rejects_canonicalization_with_comments() {
+        let with_comments = r#"Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#WithComments""#;
+        let exclusive = r#"Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#""#;
+        let expected = "Canonicalization with comments (http://www.w3.org/2001/10/xml-exc-c14n#WithComments) is not supported";
+
+        // First the CanonicalizationMethod of SignedInfo, then the Transform of the reference
+        let signed_info = SIGNED_RESPONSE.replacen(exclusive, with_comments, 1);
+        assert_ne!(signed_info, SIGNED_RESPONSE);
+        assert!(error(&signed_info, &options(SIGNING_CERTIFICATE)).starts_with(expected));
+
+        let first = SIGNED_RESPONSE.find(exclusive).unwrap() + exclusive.len();
+        let transform = format!(
+            "{}{}",
+            &SIGNED_RESPONSE[..first],
+            SIGNED_RESPONSE[first..].replacen(exclusive, with_comments, 1)
+        );
+        assert_ne!(transform, SIGNED_RESPONSE);
+        assert!(error(&transform, &options(SIGNING_CERTIFICATE)).starts_with(expected));
+    }
+
+    #[test]
+    fn rejects_duplicate_signed_id() {
+        let start = SIGNED_RESPONSE.find("<Assertion ").unwrap();
+        let evil = r#"<Assertion ID="_assert1" xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><Issuer>https://sts.windows.net/tenant-1/</Issuer></Assertion>"#;
+        let wrapped = format!(
+            "{}{}{}",
+            &SIGNED_RESPONSE[..start],
+            evil,
+            &SIGNED_RESPONSE[start..]
+        );
+
+        assert!(verify_saml_response(&wrapped, &options(SIGNING_CERTIFICATE)).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_id_nested_in_the_response() {
+        let evil = r#"<samlp:Extensions><Assertion ID="_assert1" xmlns="urn:oasis:names:tc:SAML:2.0:assertion"/></samlp:Extensions>"#;
+        let wrapped = SIGNED_RESPONSE.replace("<samlp:Status>", &format!("{}<samlp:Status>", evil));
+
+        assert!(error(&wrapped, &options(SIGNING_CERTIFICATE)).contains("Multiple elements"));
+    }
+
+    #[test]
+    fn rejects_second_unsigned_assertion() {
+        let evil = r#"<Assertion ID="_evil" xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><Issuer>https://sts.windows.net/tenant-1/</Issuer><AttributeStatement><Attribute Name="https://aws.amazon.com/SAML/Attributes/Role"><AttributeValue>arn:aws:iam::999999999999:role/Admin,arn:aws:iam::999999999999:saml-provider/AzureAD</AttributeValue></Attribute></AttributeStatement></Assertion>"#;
+        let wrapped =
+            SIGNED_RESPONSE.replace("</samlp:Response>", &format!("{}</samlp:Response>", evil));
+
+        assert!(error(&wrapped, &options(SIGNING_CERTIFICATE)).contains("more than one assertion"));
+    }
+
+    #[test]
+    fn rejects_unknown_certificate() {
+        let message = error(SIGNED_RESPONSE, &options(ROTATED_CERTIFICATE));
+
+        assert!(message.contains("rotated its signing certificate"));
+    }
+
+    #[test]
+    fn not_before_allows_clock_skew() {
+        let saml_response = SamlResponse::from_xml(SIGNED_RESPONSE).unwrap();
+        let mut options = options(SIGNING_CERTIFICATE);
+        options.now = instant("2026-10-18T20:43:06Z");
+
+        verify_conditions(&saml_response, &options).unwrap();
+
+        options.allowed_clock_skew = Duration::zero();
+        let message = verify_conditions(&saml_response, &options)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("not valid before"));
+    }
+
+    #[test]
+    fn not_on_or_after_allows_clock_skew() {
+        let mut saml_response = SamlResponse::from_xml(SIGNED_RESPONSE).unwrap();
+        saml_response.assertion.subject = None;
+        let mut options = options(SIGNING_CERTIFICATE);
+        options.now = instant("2026-10-18T21:51:06Z");
+
+        verify_conditions(&saml_response, &options).unwrap();
+
+        options.allowed_clock_skew = Duration::zero();
+        let message = verify_conditions(&saml_response, &options)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("expired at"));
+    }
+
+    #[test]
+    fn rejects_expired_subject_confirmation() {
+        let saml_response = SamlResponse::from_xml(SIGNED_RESPONSE).unwrap();
+        let mut options = options(SIGNING_CERTIFICATE);
+        options.now = instant("2026-10-18T21:00:00Z");
+
+        let message = verify_conditions(&saml_response, &options)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("subject confirmation expired"));
+    }
+
+    #[test]
+    fn rejects_audience_mismatch() {
+        let saml_response = SamlResponse::from_xml(SIGNED_RESPONSE).unwrap();
+        let mut options = options(SIGNING_CERTIFICATE);
+        options.audience = Some("urn:amazon:webservices:govcloud".to_string());
+
+        let message = verify_conditions(&saml_response, &options)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("is for audience"));
+    }
+
+    #[test]
+    fn rejects_missing_audience() {
+        let mut saml_response = SamlResponse::from_xml(SIGNED_RESPONSE).unwrap();
+        if let Some(conditions) = saml_response.assertion.conditions.as_mut() {
+            conditions.audiences.clear();
+        }
+
+        let message = verify_conditions(&saml_response, &options(SIGNING_CERTIFICATE))
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("no audience restriction"));
+    }
+
+    #[test]
+    fn parses_certificates_from_xml() {
+        let certificates = SigningCertificate::parse_all(SIGNED_RESPONSE).unwrap();
+        let expected = SigningCertificate::parse_all(SIGNING_CERTIFICATE).unwrap();
+
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(certificates[0].der, expected[0].der);
+
+        let commented = SIGNED_RESPONSE.replace(
+            "<samlp:Status>",
+            "<!-- <X509Certificate>bm90IGEgY2VydGlmaWNhdGU=</X509Certificate> --><samlp:Status>",
+        );
+        assert_eq!(SigningCertificate::parse_all(&commented).unwrap().len(), 1);
+    }
+}
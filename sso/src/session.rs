@@ -0,0 +1,225 @@
+use crate::saml_response::SamlAssertion;
+use anyhow::{anyhow, bail, Result};
+use aws_sdk_sts::types::{PolicyDescriptorType, Tag};
+use file_manager::aws_config::AwsConfig;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Session policies, tags and source identity requested for a profile's session
+#[derive(Clone, Debug, Default)]
+pub struct SessionOptions {
+    pub policy: Option<String>,
+    pub policy_arns: Vec<String>,
+    pub tags: BTreeMap<String, String>,
+    pub transitive_tag_keys: Vec<String>,
+    pub source_identity: Option<String>,
+}
+
+impl SessionOptions {
+    pub fn from_config(config: &AwsConfig) -> Result<Self> {
+        let policy = match &config.azure_session_policy {
+            Some(path) => Some(
+                std::fs::read_to_string(Path::new(path))
+                    .map_err(|e| anyhow!("Unable to read the session policy {}: {}", path, e))?
+                    .trim()
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        let tags = split_list(config.azure_session_tags.as_deref())
+            .into_iter()
+            .map(|tag| match tag.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => {
+                    Ok((key.trim().to_string(), value.trim().to_string()))
+                }
+                _ => bail!(
+                    "Invalid azure_session_tags entry '{}', expected Key=Value",
+                    tag
+                ),
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        let transitive_tag_keys = split_list(config.azure_transitive_tag_keys.as_deref());
+        if let Some(key) = transitive_tag_keys
+            .iter()
+            .find(|key| !tags.contains_key(*key))
+        {
+            bail!(
+                "Transitive tag key '{}' is not set in azure_session_tags",
+                key
+            );
+        }
+
+        Ok(Self {
+            policy,
+            policy_arns: split_list(config.azure_session_policy_arns.as_deref()),
+            tags,
+            transitive_tag_keys,
+            source_identity: config.azure_source_identity.clone(),
+        })
+    }
+
+    /// Only the policies, `AssumeRoleWithSAML` takes its tags and source identity from the assertion
+    pub fn policies_only(&self) -> Self {
+        Self {
+            policy: self.policy.clone(),
+            policy_arns: self.policy_arns.clone(),
+            ..Self::default()
+        }
+    }
+
+    pub fn has_tags_or_source_identity(&self) -> bool {
+        !self.tags.is_empty() || self.source_identity.is_some()
+    }
+
+    pub fn sts_policy_arns(&self) -> Option<Vec<PolicyDescriptorType>> {
+        if self.policy_arns.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.policy_arns
+                .iter()
+                .map(|arn| PolicyDescriptorType::builder().arn(arn).build())
+                .collect(),
+        )
+    }
+
+    pub fn sts_tags(&self) -> Option<Vec<Tag>> {
+        if self.tags.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.tags
+                .iter()
+                .map(|(key, value)| Tag::builder().key(key).value(value).build())
+                .collect(),
+        )
+    }
+
+    pub fn sts_transitive_tag_keys(&self) -> Option<Vec<String>> {
+        Some(self.transitive_tag_keys.clone()).filter(|keys| !keys.is_empty())
+    }
+}
+
+/// The session created and what was applied to it, shown after login for access reviews
+#[derive(Clone, Debug, Default)]
+pub struct SessionSummary {
+    pub role_arn: String,
+    pub session_arn: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    pub transitive_tag_keys: BTreeSet<String>,
+    pub source_identity: Option<String>,
+    pub policy: bool,
+    pub policy_arns: Vec<String>,
+}
+
+impl SessionSummary {
+    /// A session from `AssumeRoleWithSAML`, tagged with the assertion's `PrincipalTag` attributes
+    pub fn from_assertion(
+        role_arn: &str,
+        session_arn: Option<&str>,
+        assertion: &SamlAssertion,
+        options: &SessionOptions,
+        source_identity: Option<&str>,
+    ) -> Self {
+        Self {
+            role_arn: role_arn.to_string(),
+            session_arn: session_arn.map(|arn| arn.to_string()),
+            tags: assertion.principal_tags(),
+            transitive_tag_keys: assertion.transitive_tag_keys().into_iter().collect(),
+            source_identity: source_identity.map(|identity| identity.to_string()),
+            policy: options.policy.is_some(),
+            policy_arns: options.policy_arns.clone(),
+        }
+    }
+
+    /// A session from `AssumeRole`, keeping the transitive tags of the session it was assumed from
+    pub fn chain(
+        previous: Option<&Self>,
+        role_arn: &str,
+        session_arn: Option<&str>,
+        options: &SessionOptions,
+        source_identity: Option<&str>,
+    ) -> Self {
+        let mut tags = BTreeMap::new();
+        let mut transitive_tag_keys = BTreeSet::new();
+
+        if let Some(previous) = previous {
+            for key in &previous.transitive_tag_keys {
+                if let Some(value) = previous.tags.get(key) {
+                    let _ = tags.insert(key.clone(), value.clone());
+                    let _ = transitive_tag_keys.insert(key.clone());
+                }
+            }
+        }
+        tags.extend(options.tags.clone());
+        transitive_tag_keys.extend(options.transitive_tag_keys.iter().cloned());
+
+        Self {
+            role_arn: role_arn.to_string(),
+            session_arn: session_arn.map(|arn| arn.to_string()),
+            tags,
+            transitive_tag_keys,
+            // Once set, the source identity stays with every session chained from it
+            source_identity: source_identity
+                .map(|identity| identity.to_string())
+                .or_else(|| previous.and_then(|previous| previous.source_identity.clone())),
+            policy: options.policy.is_some(),
+            policy_arns: options.policy_arns.clone(),
+        }
+    }
+}
+
+impl Display for SessionSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Session for {}", self.role_arn)?;
+
+        if let Some(session_arn) = &self.session_arn {
+            writeln!(f, "  {:<18}{}", "Session ARN:", session_arn)?;
+        }
+
+        if let Some(source_identity) = &self.source_identity {
+            writeln!(f, "  {:<18}{}", "Source Identity:", source_identity)?;
+        }
+
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .map(|(key, value)| {
+                    if self.transitive_tag_keys.contains(key) {
+                        format!("{}={} (transitive)", key, value)
+                    } else {
+                        format!("{}={}", key, value)
+                    }
+                })
+                .collect();
+            writeln!(f, "  {:<18}{}", "Session Tags:", tags.join(", "))?;
+        }
+
+        if self.policy || !self.policy_arns.is_empty() {
+            let mut policies = Vec::new();
+            if self.policy {
+                policies.push("inline");
+            }
+            policies.extend(self.policy_arns.iter().map(|arn| arn.as_str()));
+            writeln!(f, "  {:<18}{}", "Session Policies:", policies.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn split_list(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
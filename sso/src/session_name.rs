@@ -0,0 +1,122 @@
+use file_manager::clock_skew::ClockSkew;
+use std::process::Command;
+
+const MAX_LENGTH: usize = 64;
+
+/// Renders `azure_session_name_template`, which supports `{username}`, `{hostname}`, `{profile}` and `{timestamp}`
+pub fn render_session_name(template: &str, profile_name: &str) -> String {
+    let name = template
+        .replace("{username}", &username().unwrap_or("unknown".to_string()))
+        .replace("{hostname}", &hostname().unwrap_or("unknown".to_string()))
+        .replace("{profile}", profile_name.trim_start_matches("profile "))
+        .replace(
+            "{timestamp}",
+            &ClockSkew::now().format("%Y%m%dT%H%M%SZ").to_string(),
+        );
+
+    sanitize_session_name(&name)
+}
+
+/// STS only accepts `[\w+=,.@-]{2,64}`, so anything else becomes `-`
+fn sanitize_session_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_+=,.@-".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(MAX_LENGTH)
+        .collect();
+
+    while sanitized.len() < 2 {
+        sanitized.push('-');
+    }
+
+    sanitized
+}
+
+fn username() -> Option<String> {
+    ["USER", "USERNAME", "LOGNAME"]
+        .iter()
+        .find_map(|key| std::env::var(key).ok())
+        .filter(|username| !username.trim().is_empty())
+}
+
+/// The short host name, e.g. `corp-laptop` for `corp-laptop.corp.example.com`
+fn hostname() -> Option<String> {
+    let hostname = ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|key| std::env::var(key).ok())
+        .or_else(|| {
+            let output = Command::new("hostname").output().ok()?;
+            String::from_utf8(output.stdout).ok()
+        })?;
+
+    hostname
+        .trim()
+        .split('.')
+        .next()
+        .filter(|hostname| !hostname.is_empty())
+        .map(|hostname| hostname.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!(
+            render_session_name("aws-azure-login-{profile}", "profile dev"),
+            "aws-azure-login-dev"
+        );
+        assert_eq!(
+            render_session_name("{username}@{hostname}", "dev"),
+            sanitize_session_name(&format!(
+                "{}@{}",
+                username().unwrap_or("unknown".to_string()),
+                hostname().unwrap_or("unknown".to_string())
+            ))
+        );
+
+        let timestamp = render_session_name("{profile}-{timestamp}", "default");
+        assert!(
+            Regex::new(r"^default-\d{8}T\d{6}Z$")
+                .unwrap()
+                .is_match(&timestamp),
+            "{}",
+            timestamp
+        );
+    }
+
+    #[test]
+    fn replaces_invalid_characters() {
+        assert_eq!(
+            sanitize_session_name("jane doe/admin:ops+1=a,b.c@d_e-f"),
+            "jane-doe-admin-ops+1=a,b.c@d_e-f"
+        );
+        assert_eq!(sanitize_session_name("zoë"), "zo-");
+    }
+
+    #[test]
+    fn truncates_overlong_names() {
+        let sanitized = sanitize_session_name(&"a".repeat(100));
+
+        assert_eq!(sanitized.len(), MAX_LENGTH);
+        assert_eq!(
+            sanitize_session_name(&"a".repeat(MAX_LENGTH)).len(),
+            MAX_LENGTH
+        );
+    }
+
+    #[test]
+    fn pads_short_names() {
+        assert_eq!(sanitize_session_name(""), "--");
+        assert_eq!(sanitize_session_name("a"), "a-");
+        assert_eq!(render_session_name("", "dev"), "--");
+    }
+}
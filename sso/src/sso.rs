@@ -1,71 +1,155 @@
-use crate::saml_request::create_login_url;
-use crate::saml_response::{parse_roles_from_saml_response, Role};
+use crate::aad_error::AzureAdError;
+use crate::clock::observe_server_time;
+use crate::helpers::{base64_decode_to_string, format_duration};
+use crate::partition::StsEndpoint;
+use crate::role_chain::{assume_chained_role, resolve_source_chain, ChainedRole};
+use crate::role_filter::RoleFilter;
+use crate::saml_request::{assertion_consumer_service_url, create_login_request};
+use crate::saml_response::decryption::DecryptionKey;
+use crate::saml_response::verification::{verify_saml_response, VerificationOptions};
+use crate::saml_response::{Role, SamlResponse};
+use crate::session::{SessionOptions, SessionSummary};
+use crate::sts_retry::StsRetry;
 use anyhow::{anyhow, bail, Result};
-use aws_sdk_sts::config::Region;
+use aws_sdk_sts::error::ProvideErrorMetadata;
 use aws_smithy_types::date_time::Format;
-use chrono::Utc;
+use base64::engine::general_purpose;
+use base64::Engine;
+use chrono::{DateTime, Utc};
 use crossbeam::channel;
+use crossbeam::channel::RecvTimeoutError;
 use dialoguer::theme::ColorfulTheme;
-use dialoguer::{Input, Select};
+use dialoguer::{Confirm, FuzzySelect, Input};
 use directories::UserDirs;
+use file_manager::account_alias::AccountAlias;
 use file_manager::aws_config::AwsConfig;
 use file_manager::aws_credential::AwsCredential;
+use file_manager::chain_cache::ChainCache;
+use file_manager::clock_skew::ClockSkew;
+use file_manager::profile_state::ProfileState;
+use file_manager::role_state::RoleState;
+use futures::future::join_all;
 use headless_chrome::browser::tab::RequestPausedDecision;
 use headless_chrome::browser::transport::{SessionId, Transport};
 use headless_chrome::protocol::cdp::Fetch::events::RequestPausedEvent;
 use headless_chrome::protocol::cdp::Fetch::{RequestPattern, RequestStage};
 use headless_chrome::protocol::cdp::Target::CreateTarget;
-use headless_chrome::{Browser, LaunchOptions};
-use log::info;
+use headless_chrome::{Browser, LaunchOptions, Tab};
+use log::{info, warn};
 use maplit::hashmap;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use shared::args::Args;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 use url::form_urlencoded;
 
+static MAXIMUM_DURATION_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"less than or equal to (\d+)").unwrap());
+
 pub async fn login(
     configs: &HashMap<String, AwsConfig>,
     credentials: &mut HashMap<String, AwsCredential>,
     profile_name: &str,
     args: &Args,
 ) -> Result<AwsCredential> {
-    if !args.force {
-        let credential = AwsCredential::get(profile_name, credentials);
-        if credential.is_ok() {
-            let credential = credential.unwrap();
-            if !credential.is_profile_about_to_expire() {
-                return Ok(credential);
-            }
+    let (credential, summary) = login_session(configs, credentials, profile_name, args).await?;
+    if let Some(summary) = summary {
+        report_session(&summary);
+    }
+
+    Ok(credential)
+}
+
+/// Logs into the profile, returning the session summary when new credentials were fetched
+async fn login_session(
+    configs: &HashMap<String, AwsConfig>,
+    credentials: &mut HashMap<String, AwsCredential>,
+    profile_name: &str,
+    args: &Args,
+) -> Result<(AwsCredential, Option<SessionSummary>)> {
+    if !args.force && args.saml_file.is_none() {
+        if let Some(credential) = cached_credential(profile_name, credentials) {
+            return Ok((credential, None));
         }
     }
 
     let config = AwsConfig::get(profile_name, configs)?;
 
+    let (base_profile, hops) = resolve_source_chain(profile_name, configs)?;
+    if !hops.is_empty() {
+        info!(
+            "Logging into profile: {} from {}",
+            profile_name, base_profile
+        );
+
+        // A valid source credential is reused even with --force, only the chained roles are refreshed
+        let (mut credential, mut summary) = match cached_credential(&base_profile, credentials) {
+            Some(credential) => (credential, None),
+            None => Box::pin(login_session(configs, credentials, &base_profile, args)).await?,
+        };
+        for hop in &hops {
+            let (hop_credential, hop_summary) = assume_chained_role(
+                &hop.profile_name,
+                &credential,
+                summary.as_ref(),
+                &hop.chained_role,
+                &hop.session,
+                &hop.sts,
+            )
+            .await?;
+            credential = hop_credential;
+            summary = Some(hop_summary);
+        }
+
+        AwsCredential::upsert(profile_name, &credential, credentials)?;
+        AwsCredential::write(credentials)?;
+
+        return Ok((credential, summary));
+    }
+
+    let session = SessionOptions::from_config(&config)?;
+
+    if let Some((credential, summary)) =
+        chain_from_cache(profile_name, &config, &session, args).await?
+    {
+        AwsCredential::upsert(profile_name, &credential, credentials)?;
+        AwsCredential::write(credentials)?;
+
+        return Ok((credential, Some(summary)));
+    }
+
     info!("Logging into profile: {}", profile_name);
 
-    let saml = perform_login(&config, args)?;
-    let roles = parse_roles_from_saml_response(&saml)?;
+    let (saml, saml_response) = fetch_assertion(&config, args)?;
+    let roles = saml_response.assertion.roles()?;
 
-    let (role, duration_hours) = role_and_duration(
+    let (role, duration_seconds) = role_and_duration(
+        profile_name,
+        &config,
         roles,
-        config.azure_default_role_arn.clone(),
-        config.azure_default_duration_hours,
+        args.role.as_deref(),
+        saml_response.assertion.session_duration(),
     )?;
 
-    let credential = assume_role(
+    let session_login = assume_role(
         profile_name,
         &saml,
+        &saml_response,
         &role,
-        duration_hours,
-        config.region.to_owned(),
+        &saml_session(profile_name, &config, &session),
+        &StsEndpoint::from_config(&config, &role.role_arn)?,
+        duration_seconds,
     )
     .await?;
+    let (credential, summary) =
+        chain_from_saml_role(profile_name, &config, &session, session_login).await?;
 
     AwsCredential::upsert(profile_name, &credential, credentials)?;
     AwsCredential::write(credentials)?;
 
-    Ok(credential)
+    Ok((credential, Some(summary)))
 }
 
 pub async fn login_all(
@@ -73,17 +157,458 @@ pub async fn login_all(
     credentials: &mut HashMap<String, AwsCredential>,
     args: &Args,
 ) -> Result<()> {
-    for profile_name in configs.keys() {
-        login(configs, credentials, profile_name, args).await?;
+    let LoginPlan {
+        mut groups,
+        mut chained_profiles,
+    } = LoginPlan::new(configs, credentials, args.force);
+
+    let mut failed_profiles = Vec::new();
+
+    for profiles in groups.values_mut() {
+        profiles.sort_by_key(|(profile_name, _)| *profile_name);
+
+        // Profiles chaining from cached SAML role credentials don't need the browser login
+        let mut pending = Vec::new();
+        for &(profile_name, config) in profiles.iter() {
+            let cached = match SessionOptions::from_config(config) {
+                Ok(session) => chain_from_cache(profile_name, config, &session, args).await,
+                Err(error) => Err(error),
+            };
+            match cached {
+                Ok(Some((credential, summary))) => {
+                    AwsCredential::upsert(profile_name, &credential, credentials)?;
+                    report_session(&summary);
+                }
+                Ok(None) => pending.push((profile_name, config)),
+                Err(error) => {
+                    warn!("Unable to log into {}: {:#}", profile_name, error);
+                    failed_profiles.push(profile_name);
+                }
+            }
+        }
+        if pending.is_empty() {
+            continue;
+        }
+
+        match login_group(&pending, args).await {
+            Ok(results) => {
+                for (profile_name, result) in results {
+                    match result {
+                        Ok((credential, summary)) => {
+                            AwsCredential::upsert(profile_name, &credential, credentials)?;
+                            report_session(&summary);
+                        }
+                        Err(error) => {
+                            warn!("Unable to log into {}: {:#}", profile_name, error);
+                            failed_profiles.push(profile_name);
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                let profile_names: Vec<&str> = pending.iter().map(|(name, _)| *name).collect();
+                warn!(
+                    "Unable to log into {}: {:#}",
+                    profile_names.join(", "),
+                    error
+                );
+                failed_profiles.extend(profile_names);
+            }
+        }
+    }
+
+    chained_profiles.sort();
+    for profile_name in chained_profiles {
+        if let Err(error) = login(configs, credentials, profile_name, args).await {
+            warn!("Unable to log into {}: {:#}", profile_name, error);
+            failed_profiles.push(profile_name);
+        }
+    }
+
+    AwsCredential::write(credentials)?;
+
+    if !failed_profiles.is_empty() {
+        bail!("Unable to log into {}", failed_profiles.join(", "));
+    }
+
+    Ok(())
+}
+
+type SessionLogin = (AwsCredential, SessionSummary);
+
+/// The profiles `--all` logs into, grouped by the browser login they share
+struct LoginPlan<'a> {
+    groups: BTreeMap<AssertionKey, Vec<(&'a str, &'a AwsConfig)>>,
+    /// Logged in after the groups, so they can reuse the source profile's fresh credentials
+    chained_profiles: Vec<&'a str>,
+}
+
+impl<'a> LoginPlan<'a> {
+    fn new(
+        configs: &'a HashMap<String, AwsConfig>,
+        credentials: &HashMap<String, AwsCredential>,
+        force: bool,
+    ) -> Self {
+        // Profiles that would get the same assertion share one browser login
+        let mut groups: BTreeMap<AssertionKey, Vec<(&str, &AwsConfig)>> = BTreeMap::new();
+        let mut chained_profiles = Vec::new();
+        for (profile_name, config) in configs {
+            if !force && cached_credential(profile_name, credentials).is_some() {
+                continue;
+            }
+            if ChainedRole::from_source_profile(config).is_some() {
+                chained_profiles.push(profile_name.as_str());
+                continue;
+            }
+            groups
+                .entry(AssertionKey::from_config(config))
+                .or_default()
+                .push((profile_name, config));
+        }
+
+        Self {
+            groups,
+            chained_profiles,
+        }
+    }
+}
+
+/// Everything in a profile that shapes the login or the assertion, a group logs in with its first profile's config
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct AssertionKey {
+    tenant_id: Option<String>,
+    app_id_uri: Option<String>,
+    cloud: Option<String>,
+    authority_host: Option<String>,
+    idp_initiated_url: Option<String>,
+    assertion_consumer_service_url: String,
+    username: Option<String>,
+    select_account: Option<bool>,
+    domain_hint: Option<String>,
+    relay_state: Option<String>,
+    remember_me: Option<bool>,
+    force_authn: Option<bool>,
+    is_passive: Option<bool>,
+    name_id_format: Option<String>,
+    authn_context: Option<String>,
+    authn_context_comparison: Option<String>,
+    verify_saml: Option<bool>,
+    signing_certificate: Option<String>,
+    idp_entity_id: Option<String>,
+    saml_decryption_key: Option<String>,
+}
+
+impl AssertionKey {
+    fn from_config(config: &AwsConfig) -> Self {
+        Self {
+            tenant_id: config.azure_tenant_id.clone(),
+            app_id_uri: config.azure_app_id_uri.clone(),
+            cloud: config.azure_cloud.clone(),
+            authority_host: config.azure_authority_host.clone(),
+            idp_initiated_url: config.azure_idp_initiated_url.clone(),
+            // Covers the region, partition and azure_acs_url
+            assertion_consumer_service_url: assertion_consumer_service_url(config),
+            username: config.azure_default_username.clone(),
+            select_account: config.azure_select_account,
+            domain_hint: config.azure_domain_hint.clone(),
+            relay_state: config.azure_relay_state.clone(),
+            remember_me: config.azure_default_remember_me,
+            force_authn: config.azure_force_authn,
+            is_passive: config.azure_is_passive,
+            name_id_format: config.azure_name_id_format.clone(),
+            authn_context: config.azure_authn_context.clone(),
+            authn_context_comparison: config.azure_authn_context_comparison.clone(),
+            verify_saml: config.azure_verify_saml,
+            signing_certificate: config.azure_signing_certificate.clone(),
+            idp_entity_id: config.azure_idp_entity_id.clone(),
+            saml_decryption_key: config.azure_saml_decryption_key.clone(),
+        }
+    }
+}
+
+/// Assumes the default role of every profile concurrently, from a single login
+async fn login_group<'a>(
+    profiles: &[(&'a str, &AwsConfig)],
+    args: &Args,
+) -> Result<Vec<(&'a str, Result<SessionLogin>)>> {
+    let (_, config) = profiles[0];
+    let profile_names: Vec<&str> = profiles.iter().map(|(name, _)| *name).collect();
+
+    info!("Logging into profiles: {}", profile_names.join(", "));
+
+    let (saml, saml_response) = fetch_assertion(config, args)?;
+    let roles = saml_response.assertion.roles()?;
+
+    // Role selection may prompt, so it happens before any of the requests are sent
+    let selections: Vec<_> = profiles
+        .iter()
+        .map(|(profile_name, config)| {
+            let selection = role_and_duration(
+                profile_name,
+                config,
+                roles.clone(),
+                None,
+                saml_response.assertion.session_duration(),
+            );
+            (*profile_name, *config, selection)
+        })
+        .collect();
+
+    let not_on_or_after = saml_response
+        .assertion
+        .conditions
+        .as_ref()
+        .and_then(|conditions| conditions.not_on_or_after);
+    if not_on_or_after.is_some_and(|not_on_or_after| ClockSkew::now() >= not_on_or_after) {
+        bail!("The SAML assertion expired before the roles were assumed, please try again");
+    }
+
+    let (saml, saml_response) = (&saml, &saml_response);
+    let requests = selections
+        .into_iter()
+        .map(|(profile_name, config, selection)| async move {
+            let (role, duration_seconds) = selection?;
+            let session = SessionOptions::from_config(config)?;
+            let session_login = assume_role(
+                profile_name,
+                saml,
+                saml_response,
+                &role,
+                &saml_session(profile_name, config, &session),
+                &StsEndpoint::from_config(config, &role.role_arn)?,
+                duration_seconds,
+            )
+            .await?;
+            chain_from_saml_role(profile_name, config, &session, session_login).await
+        });
+    let results = join_all(requests).await;
+
+    Ok(profile_names.into_iter().zip(results).collect())
+}
+
+/// Assumes the `azure_chain_role_arn` from cached credentials of the SAML role, skipping the login
+async fn chain_from_cache(
+    profile_name: &str,
+    config: &AwsConfig,
+    session: &SessionOptions,
+    args: &Args,
+) -> Result<Option<SessionLogin>> {
+    if args.force || args.saml_file.is_some() || config.azure_chain_cache != Some(true) {
+        return Ok(None);
+    }
+
+    let (chained_role, source) = match (
+        ChainedRole::from_azure_chain(config),
+        ChainCache::get(&AwsConfig::sanitize_profile_name(profile_name)),
+    ) {
+        (Some(chained_role), Some(source)) => (chained_role, source),
+        _ => return Ok(None),
+    };
+
+    info!(
+        "Assuming {} from cached credentials for profile: {}",
+        chained_role.role_arn, profile_name
+    );
+
+    let session_login = assume_chained_role(
+        profile_name,
+        &source,
+        None,
+        &chained_role,
+        session,
+        &StsEndpoint::from_config(config, &chained_role.role_arn)?,
+    )
+    .await?;
+    Ok(Some(session_login))
+}
+
+/// Runs the `azure_chain_role_arn` hop, if there is one, from the credentials of the SAML role
+async fn chain_from_saml_role(
+    profile_name: &str,
+    config: &AwsConfig,
+    session: &SessionOptions,
+    (credential, summary): SessionLogin,
+) -> Result<SessionLogin> {
+    let chained_role = match ChainedRole::from_azure_chain(config) {
+        Some(chained_role) => chained_role,
+        None => return Ok((credential, summary)),
+    };
+
+    if config.azure_chain_cache == Some(true) {
+        let cache_key = AwsConfig::sanitize_profile_name(profile_name);
+        if let Err(error) = ChainCache::upsert(&cache_key, &credential) {
+            warn!("Unable to cache the SAML role credentials: {}", error);
+        }
+    }
+
+    assume_chained_role(
+        profile_name,
+        &credential,
+        Some(&summary),
+        &chained_role,
+        session,
+        &StsEndpoint::from_config(config, &chained_role.role_arn)?,
+    )
+    .await
+}
+
+/// The session options for `AssumeRoleWithSAML`, policies go on the chained role instead when there is one
+fn saml_session(
+    profile_name: &str,
+    config: &AwsConfig,
+    session: &SessionOptions,
+) -> SessionOptions {
+    if ChainedRole::from_azure_chain(config).is_some() {
+        return SessionOptions::default();
+    }
+
+    if session.has_tags_or_source_identity() {
+        warn!(
+            "Session tags and source identity only apply to chained roles, ignoring them for {}",
+            profile_name
+        );
+    }
+    session.policies_only()
+}
+
+fn report_session(summary: &SessionSummary) {
+    info!("{}", summary.to_string().trim_end());
+}
+
+fn cached_credential(
+    profile_name: &str,
+    credentials: &HashMap<String, AwsCredential>,
+) -> Option<AwsCredential> {
+    AwsCredential::get(profile_name, credentials)
+        .ok()
+        .filter(|credential| !credential.is_profile_about_to_expire())
+}
+
+/// Gets the SAML response from the browser or `--saml-file`, returning it as base64 and parsed
+fn fetch_assertion(config: &AwsConfig, args: &Args) -> Result<(String, SamlResponse)> {
+    let (saml, received_at) = match &args.saml_file {
+        Some(path) => (read_saml_response(path)?, None),
+        None => {
+            let (saml, received_at) = perform_login(config, args)?;
+            (saml, Some(received_at))
+        }
+    };
+
+    // Encrypted assertions are only decrypted to read them, STS is sent the original
+    let saml_response =
+        SamlResponse::from_base64_with_key(&saml, DecryptionKey::from_config(config)?.as_ref())?;
+
+    // A fresh response was issued just before it arrived, so its IssueInstant shows how far off the local clock is
+    if let (Some(issue_instant), Some(received_at)) = (saml_response.issue_instant, received_at) {
+        observe_server_time(issue_instant, received_at, "Azure AD");
+    }
+
+    if config.azure_verify_saml == Some(true) {
+        let verified = verify_saml_response(
+            &base64_decode_to_string(&saml)?,
+            &VerificationOptions::from_config(config)?,
+        )?;
+        info!(
+            "SAML {} signature verified with certificate {}",
+            verified.signed_element, verified.certificate_thumbprint
+        );
+    }
+
+    Ok((saml, saml_response))
+}
+
+/// Performs the browser login only, returning the base64 SAML response
+pub fn fetch_saml(
+    configs: &HashMap<String, AwsConfig>,
+    profile_name: &str,
+    args: &Args,
+) -> Result<String> {
+    let config = AwsConfig::get(profile_name, configs)?;
+
+    info!("Fetching SAML response for profile: {}", profile_name);
+
+    Ok(perform_login(&config, args)?.0)
+}
+
+/// Performs the login only, returning every role in the SAML assertion
+pub fn fetch_roles(
+    configs: &HashMap<String, AwsConfig>,
+    profile_name: &str,
+    args: &Args,
+) -> Result<Vec<Role>> {
+    let config = AwsConfig::get(profile_name, configs)?;
+
+    info!("Discovering roles for profile: {}", profile_name);
+
+    let (_, saml_response) = fetch_assertion(&config, args)?;
+    filter_roles(
+        &config,
+        saml_response.assertion.roles()?,
+        &read_account_aliases(),
+    )
+}
+
+/// Performs the browser login only, writing the base64 SAML response to a file or `-` for stdout
+pub fn export_saml(
+    configs: &HashMap<String, AwsConfig>,
+    profile_name: &str,
+    args: &Args,
+    path: &str,
+) -> Result<()> {
+    let saml = fetch_saml(configs, profile_name, args)?;
+
+    if path == "-" {
+        println!("{}", saml);
+    } else {
+        std::fs::write(path, format!("{}\n", saml))?;
+        info!("SAML response written to {}", path);
     }
 
     Ok(())
 }
 
-fn perform_login(profile: &AwsConfig, args: &Args) -> Result<String> {
-    let mut saml_response_result = saml_sso_fetch(profile, args, !args.debug);
+/// Reads a SAML response from a file or `-` for stdin, accepting base64, XML or a posted form body
+pub fn read_saml_response(path: &str) -> Result<String> {
+    let contents = if path == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(path).map_err(|e| anyhow!("Unable to read {}: {}", path, e))?
+    };
+    let contents = contents.trim().trim_start_matches('\u{feff}');
+
+    let saml = if contents.starts_with('<') {
+        general_purpose::STANDARD.encode(contents)
+    } else if contents.contains("SAMLResponse=") {
+        form_urlencoded::parse(contents.as_bytes())
+            .find(|(key, _)| key == "SAMLResponse")
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_default()
+    } else {
+        contents.chars().filter(|c| !c.is_whitespace()).collect()
+    };
+
+    if base64_decode_to_string(&saml).is_err() {
+        bail!("{} does not contain a base64 or XML SAML response", path);
+    }
+
+    Ok(saml)
+}
+
+/// Logs in through the browser, returning the base64 SAML response and the local time it arrived
+fn perform_login(profile: &AwsConfig, args: &Args) -> Result<(String, DateTime<Utc>)> {
+    let headless = !args.debug;
+    let mut saml_response_result = saml_sso_fetch(profile, args, headless);
+
+    if let Err(error) = &saml_response_result {
+        // Azure AD errors are final, unless they ask for something the user can do in the browser
+        let retry_visible = match error.downcast_ref::<AzureAdError>() {
+            Some(aad_error) => headless && aad_error.requires_interaction(),
+            None => true,
+        };
+        if !retry_visible {
+            return saml_response_result;
+        }
 
-    if saml_response_result.is_err() {
         // TODO: Make `saml_sso_fetch` return an error early if asking for details
         saml_response_result = saml_sso_fetch(profile, args, false);
     }
@@ -91,7 +616,11 @@ fn perform_login(profile: &AwsConfig, args: &Args) -> Result<String> {
     saml_response_result
 }
 
-fn saml_sso_fetch(profile: &AwsConfig, args: &Args, headless: bool) -> Result<String> {
+fn saml_sso_fetch(
+    profile: &AwsConfig,
+    args: &Args,
+    headless: bool,
+) -> Result<(String, DateTime<Utc>)> {
     let width = 425;
     let height = 550;
 
@@ -115,7 +644,20 @@ fn saml_sso_fetch(profile: &AwsConfig, args: &Args, headless: bool) -> Result<St
 
     let browser = Browser::new(launch_options_built)?;
 
-    let azure_url = create_login_url(profile)?;
+    // IdP-initiated sign on posts the assertion to the same ACS URL, so the capture below is shared
+    let (azure_url, aws_url) = match &profile.azure_idp_initiated_url {
+        Some(idp_initiated_url) => (
+            idp_initiated_url.to_owned(),
+            assertion_consumer_service_url(profile),
+        ),
+        None => {
+            let login_request = create_login_request(profile)?;
+            (
+                login_request.to_url()?,
+                login_request.authn_request.assertion_consumer_service_url,
+            )
+        }
+    };
 
     let tab = browser.new_tab_with_options(CreateTarget {
         url: azure_url.clone(),
@@ -133,7 +675,6 @@ fn saml_sso_fetch(profile: &AwsConfig, args: &Args, headless: bool) -> Result<St
         "Accept-Language" => "en"
     })?;
 
-    let aws_url = profile.azure_app_id_uri.clone().unwrap();
     let patterns = vec![RequestPattern {
         url_pattern: Some(aws_url.clone()),
         resource_Type: None,
@@ -149,7 +690,8 @@ fn saml_sso_fetch(profile: &AwsConfig, args: &Args, headless: bool) -> Result<St
               intercepted: RequestPausedEvent| {
             if intercepted.params.request.url.contains(&aws_url) {
                 let response_data = intercepted.params.request.post_data.unwrap();
-                sender.send(response_data).unwrap();
+                // Timestamped on arrival, so navigation afterwards isn't counted as clock skew
+                sender.send((response_data, Utc::now())).unwrap();
             }
 
             RequestPausedDecision::Continue(None)
@@ -158,8 +700,22 @@ fn saml_sso_fetch(profile: &AwsConfig, args: &Args, headless: bool) -> Result<St
 
     tab.reload(false, None)?; // TODO: Part 2 for interception hack, if already logged in it doesn't detect the response unless you reload the browser
 
-    let saml_response = receiver
-        .recv()?
+    let (saml_response, received_at) = loop {
+        match receiver.recv_timeout(Duration::from_millis(500)) {
+            Ok(received) => break received,
+            Err(RecvTimeoutError::Timeout) => {
+                // A visible browser stays open while the user completes MFA and similar prompts
+                if let Some(error) = detect_azure_ad_error(&tab) {
+                    if headless || !error.requires_interaction() {
+                        return Err(error.into());
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => bail!("Browser closed before login completed"),
+        }
+    };
+
+    let saml_response = saml_response
         .strip_prefix("SAMLResponse=")
         .unwrap()
         .to_string();
@@ -170,107 +726,353 @@ fn saml_sso_fetch(profile: &AwsConfig, args: &Args, headless: bool) -> Result<St
 
     tab.wait_until_navigated()?; // TODO: Allows time for the remember me response to go through and set the cookies
 
-    Ok(saml_response_decoded)
+    Ok((saml_response_decoded, received_at))
+}
+
+fn detect_azure_ad_error(tab: &Tab) -> Option<AzureAdError> {
+    let page_text = tab
+        .evaluate("document.body ? document.body.innerText : ''", false)
+        .ok()?
+        .value?;
+
+    AzureAdError::from_page_text(page_text.as_str()?)
 }
 
 fn role_and_duration(
+    profile_name: &str,
+    config: &AwsConfig,
     roles: Vec<Role>,
-    default_role_arn: Option<String>,
-    default_duration_hours: Option<u8>,
-) -> Result<(Role, u8)> {
-    let mut duration_hours: u8 = default_duration_hours.unwrap_or_default();
+    requested_role: Option<&str>,
+    session_duration: Option<u32>,
+) -> Result<(Role, i32)> {
+    let aliases = read_account_aliases();
+
+    let roles = filter_roles(config, roles, &aliases)?;
 
     let selected_role = if roles.is_empty() {
         bail!("No roles found in SAML response.");
+    } else if let Some(requested_role) = requested_role {
+        find_role(&roles, requested_role, &aliases).ok_or_else(|| {
+            anyhow!(
+                "No role matching '{}' found in the SAML response.",
+                requested_role
+            )
+        })?
     } else if roles.len() == 1 {
         roles.first().unwrap().to_owned()
-    } else if let Some(default_role_arn) = &default_role_arn {
-        get_default_role(&roles, &Some(default_role_arn.clone()))?
+    } else if let Some(role) = first_available_default_role(profile_name, config, &roles, &aliases)
+    {
+        role
     } else {
-        select_role_interactively(&roles)
+        select_role_interactively(profile_name, &roles)?
     };
 
-    if default_duration_hours.is_none() {
-        duration_hours = loop {
-            let input: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Default Session Duration Hours (up to 12)")
-                .default(default_duration_hours.map_or(String::new(), |x| x.to_string()))
-                .allow_empty(true)
-                .interact_text()
-                .unwrap();
-
-            if let Ok(value) = input.parse::<u8>() {
-                if value > 0 && value <= 12 {
-                    break value;
-                }
+    let duration_seconds = match (config.azure_default_duration_hours, session_duration) {
+        (Some(hours), _) => cap_duration(hours as i32 * 60 * 60, session_duration),
+        (None, Some(session_duration)) => {
+            info!(
+                "Using the SessionDuration of {} from the SAML assertion",
+                format_duration(session_duration as i64)
+            );
+            session_duration as i32
+        }
+        (None, None) => prompt_duration_hours() as i32 * 60 * 60,
+    };
+
+    let duration_seconds = cap_to_remembered_duration(&selected_role, duration_seconds);
+
+    Ok((selected_role, duration_seconds))
+}
+
+fn cap_to_remembered_duration(role: &Role, requested_seconds: i32) -> i32 {
+    cap_to_max_duration(
+        role,
+        requested_seconds,
+        RoleState::max_session_duration(&role.role_arn),
+    )
+}
+
+fn cap_to_max_duration(role: &Role, requested_seconds: i32, max_duration: Option<u32>) -> i32 {
+    match max_duration {
+        Some(max_duration) if (max_duration as i32) < requested_seconds => {
+            info!(
+                "Using the {} maximum session duration previously accepted for {}",
+                format_duration(max_duration as i64),
+                role.role_arn
+            );
+            max_duration as i32
+        }
+        _ => requested_seconds,
+    }
+}
+
+/// Caps the requested duration to the SessionDuration the IdP allows, STS rejects anything longer
+fn cap_duration(requested_seconds: i32, session_duration: Option<u32>) -> i32 {
+    match session_duration {
+        Some(session_duration) if (session_duration as i32) < requested_seconds => {
+            warn!(
+                "Requested session duration of {} exceeds the SessionDuration of {} in the SAML assertion, reducing it to match",
+                format_duration(requested_seconds as i64),
+                format_duration(session_duration as i64)
+            );
+            session_duration as i32
+        }
+        _ => requested_seconds,
+    }
+}
+
+fn prompt_duration_hours() -> u8 {
+    loop {
+        let input: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Default Session Duration Hours (up to 12)")
+            .default(String::new())
+            .allow_empty(true)
+            .interact_text()
+            .unwrap();
+
+        if let Ok(value) = input.parse::<u8>() {
+            if value > 0 && value <= 12 {
+                break value;
             }
-        };
+        }
+    }
+}
+
+fn filter_roles(
+    config: &AwsConfig,
+    roles: Vec<Role>,
+    aliases: &HashMap<String, String>,
+) -> Result<Vec<Role>> {
+    let filter = match RoleFilter::from_config(config)? {
+        Some(filter) => filter,
+        None => return Ok(roles),
+    };
+
+    let filtered: Vec<Role> = roles
+        .into_iter()
+        .filter(|role| filter.matches(role, aliases))
+        .collect();
+    if filtered.is_empty() {
+        bail!("No roles in the SAML response match azure_role_filter.");
     }
 
-    Ok((selected_role, duration_hours))
+    Ok(filtered)
 }
 
-fn get_default_role(roles: &[Role], default_role_arn: &Option<String>) -> Result<Role> {
-    match default_role_arn {
-        Some(arn) => roles
-            .iter()
-            .find(|r| &r.role_arn == arn)
-            .cloned()
-            .ok_or_else(|| {
-                anyhow!("No role matching the default role ARN found in the SAML response.")
-            }),
-        None => bail!("No default role ARN provided and multiple roles found in SAML response."),
+fn find_role(roles: &[Role], name: &str, aliases: &HashMap<String, String>) -> Option<Role> {
+    roles
+        .iter()
+        .find(|r| role_matches(r, name, aliases))
+        .cloned()
+}
+
+/// The default role, or the first fallback role, that is in the SAML response
+fn first_available_default_role(
+    profile_name: &str,
+    config: &AwsConfig,
+    roles: &[Role],
+    aliases: &HashMap<String, String>,
+) -> Option<Role> {
+    let candidates: Vec<&str> = config
+        .azure_default_role_arn
+        .iter()
+        .chain(config.azure_fallback_roles.iter())
+        .flat_map(|names| names.split(','))
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    for (index, name) in candidates.iter().enumerate() {
+        if let Some(role) = find_role(roles, name, aliases) {
+            if index > 0 {
+                warn!(
+                    "{} is not in the SAML response, using the fallback role {}",
+                    candidates[0], role.role_arn
+                );
+            }
+            return Some(role);
+        }
+    }
+
+    if !candidates.is_empty() {
+        warn!(
+            "None of the default roles for {} are in the SAML response",
+            profile_name
+        );
     }
+
+    None
 }
 
-fn select_role_interactively(roles: &[Role]) -> Role {
-    let selection = Select::new()
-        .with_prompt("Role")
-        .default(0)
+/// Matches a role by ARN, or by `<account alias or ID>/<role name>`
+fn role_matches(role: &Role, name: &str, aliases: &HashMap<String, String>) -> bool {
+    if role.role_arn == name {
+        return true;
+    }
+
+    match name.rsplit_once('/') {
+        Some((account, role_name)) => {
+            let account = account.trim();
+            role.role_name() == role_name.trim()
+                && (role.account_id() == account
+                    || aliases.get(role.account_id()).map(|a| a.as_str()) == Some(account))
+        }
+        None => false,
+    }
+}
+
+fn role_label(role: &Role, aliases: &HashMap<String, String>) -> String {
+    match aliases.get(role.account_id()) {
+        Some(alias) => format!("{} ({}) / {}", alias, role.account_id(), role.role_name()),
+        None => format!("{} / {}", role.account_id(), role.role_name()),
+    }
+}
+
+fn read_account_aliases() -> HashMap<String, String> {
+    AccountAlias::read_aliases().unwrap_or_else(|error| {
+        warn!("Unable to read the account aliases: {}", error);
+        HashMap::new()
+    })
+}
+
+fn select_role_interactively(profile_name: &str, roles: &[Role]) -> Result<Role> {
+    let aliases = read_account_aliases();
+    let state_key = AwsConfig::sanitize_profile_name(profile_name);
+
+    // Group the roles by account, aliased accounts first
+    let mut sorted_roles: Vec<&Role> = roles.iter().collect();
+    sorted_roles.sort_by_key(|r| {
+        (
+            !aliases.contains_key(r.account_id()),
+            aliases.get(r.account_id()).cloned(),
+            r.account_id().to_string(),
+            r.role_name().to_string(),
+        )
+    });
+
+    let last_role_arn = ProfileState::get(&state_key).and_then(|state| state.last_role_arn);
+    let default_index = sorted_roles
+        .iter()
+        .position(|r| Some(&r.role_arn) == last_role_arn.as_ref())
+        .unwrap_or(0);
+
+    let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Role (type to filter)")
+        .default(default_index)
         .items(
-            &roles
+            &sorted_roles
                 .iter()
-                .map(|r| r.role_arn.as_str())
+                .map(|r| format!("{} [{}]", role_label(r, &aliases), r.role_arn))
                 .collect::<Vec<_>>(),
         )
-        .interact()
-        .unwrap();
-    roles[selection].to_owned()
+        .interact()?;
+    let role = sorted_roles[selection].to_owned();
+
+    let profile_state = ProfileState {
+        last_role_arn: Some(role.role_arn.clone()),
+    };
+    if let Err(error) = ProfileState::upsert(&state_key, &profile_state) {
+        warn!("Unable to remember the selected role: {}", error);
+    }
+
+    let save_as_default = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "Save {} as the default role for {}?",
+            role_label(&role, &aliases),
+            profile_name.trim_start_matches("profile ")
+        ))
+        .default(false)
+        .interact()?;
+    if save_as_default {
+        if let Err(error) = save_default_role(profile_name, &role) {
+            warn!("Unable to save the default role: {}", error);
+        }
+    }
+
+    Ok(role)
+}
+
+fn save_default_role(profile_name: &str, role: &Role) -> Result<()> {
+    let mut configs = AwsConfig::read_file()?;
+    let config = AwsConfig {
+        azure_default_role_arn: Some(role.role_arn.clone()),
+        ..AwsConfig::get(profile_name, &configs)?
+    };
+
+    AwsConfig::upsert(profile_name, &config, &mut configs)?;
+    AwsConfig::write(&configs)
 }
 
 async fn assume_role(
     profile_name: &str,
     assertion: &str,
+    saml_response: &SamlResponse,
     role: &Role,
-    duration_hours: u8,
-    region: Option<String>,
-) -> Result<AwsCredential> {
-    let config = if let Some(region_str) = region {
-        aws_config::from_env()
-            .region(Region::new(region_str))
-            .no_credentials()
-            .load()
-            .await
-    } else {
-        aws_config::from_env().no_credentials().load().await
-    };
+    session: &SessionOptions,
+    sts: &StsEndpoint,
+    duration_seconds: i32,
+) -> Result<SessionLogin> {
+    let sts_client = sts.client(None).await;
 
-    let sts_client = aws_sdk_sts::Client::new(&config);
+    let mut accepted_duration_seconds = duration_seconds;
+    let mut retry = StsRetry::new(
+        format!("assume {}", role.role_arn),
+        saml_response
+            .assertion
+            .conditions
+            .as_ref()
+            .and_then(|conditions| conditions.not_on_or_after),
+        "SAML assertion",
+    );
 
-    let duration_seconds = (duration_hours as i32) * 60 * 60;
+    let assume_role_response = loop {
+        let assume_role_request = sts_client
+            .assume_role_with_saml()
+            .role_arn(&role.role_arn)
+            .principal_arn(&role.principal_arn)
+            .saml_assertion(assertion)
+            .set_policy(session.policy.clone())
+            .set_policy_arns(session.sts_policy_arns())
+            .duration_seconds(accepted_duration_seconds);
 
-    let assume_role_request = sts_client
-        .assume_role_with_saml()
-        .role_arn(&role.role_arn)
-        .principal_arn(&role.principal_arn)
-        .saml_assertion(assertion)
-        .duration_seconds(duration_seconds);
+        match assume_role_request.send().await {
+            Ok(response) => break response,
+            Err(error) => match shorter_duration(&error, accepted_duration_seconds) {
+                Some(shorter_duration_seconds) => {
+                    warn!(
+                        "STS rejected a session duration of {} for {}, retrying with {}",
+                        format_duration(accepted_duration_seconds as i64),
+                        role.role_arn,
+                        format_duration(shorter_duration_seconds as i64)
+                    );
+                    accepted_duration_seconds = shorter_duration_seconds;
+                }
+                None => retry.backoff(error).await?,
+            },
+        }
+    };
 
-    let assume_role_response = assume_role_request.send().await?;
+    remember_duration(&role.role_arn, duration_seconds, accepted_duration_seconds);
 
-    let credentials = assume_role_response
-        .credentials
-        .ok_or(anyhow!("No credentials found in assume role response"))?;
+    let summary = SessionSummary::from_assertion(
+        &role.role_arn,
+        assume_role_response
+            .assumed_role_user()
+            .and_then(|user| user.arn()),
+        &saml_response.assertion,
+        session,
+        assume_role_response.source_identity(),
+    );
+    let credential = credential_from_sts(profile_name, assume_role_response.credentials)?;
+
+    Ok((credential, summary))
+}
+
+pub(crate) fn credential_from_sts(
+    profile_name: &str,
+    credentials: Option<aws_sdk_sts::types::Credentials>,
+) -> Result<AwsCredential> {
+    let credentials = credentials.ok_or(anyhow!("No credentials found in assume role response"))?;
 
     let access_key_id = credentials
         .access_key_id
@@ -301,3 +1103,191 @@ async fn assume_role(
         aws_expiration: Some(expiration),
     })
 }
+
+/// Remembers a lowered duration, and raises a remembered one once a longer duration is accepted
+fn remember_duration(role_arn: &str, requested_seconds: i32, accepted_seconds: i32) {
+    let remembered = RoleState::get(role_arn).and_then(|state| state.max_session_duration);
+    let lowered = accepted_seconds < requested_seconds;
+    let raised = remembered.is_some_and(|remembered| (remembered as i32) < accepted_seconds);
+    if !lowered && !raised {
+        return;
+    }
+
+    let role_state = RoleState {
+        max_session_duration: Some(accepted_seconds as u32),
+        learned_at: Some(Utc::now()),
+    };
+    if let Err(error) = RoleState::upsert(role_arn, &role_state) {
+        warn!("Unable to remember the session duration: {}", error);
+    }
+}
+
+/// Works out the next duration to try when STS rejects DurationSeconds for exceeding the role's maximum
+fn shorter_duration<E: ProvideErrorMetadata>(error: &E, duration_seconds: i32) -> Option<i32> {
+    let message = error.message()?;
+
+    if error.code() != Some("ValidationError")
+        || !message.to_lowercase().contains("durationseconds")
+    {
+        return None;
+    }
+
+    if let Some(maximum) = MAXIMUM_DURATION_REGEX
+        .captures(message)
+        .and_then(|captures| captures[1].parse::<i32>().ok())
+    {
+        if maximum < duration_seconds {
+            return Some(maximum);
+        }
+    }
+
+    // Role maximums are whole hours of at least one hour, so step down an hour at a time
+    if duration_seconds > 3600 {
+        Some(((duration_seconds - 1) / 3600) * 3600)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_smithy_types::error::ErrorMetadata;
+
+    fn credential() -> AwsCredential {
+        AwsCredential {
+            profile_name: None,
+            aws_access_key_id: Some("AKIAWORK".to_string()),
+            aws_secret_access_key: Some("secret".to_string()),
+            aws_session_token: Some("token".to_string()),
+            aws_expiration: Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+        }
+    }
+
+    fn chained_configs() -> HashMap<String, AwsConfig> {
+        let work = AwsConfig {
+            azure_tenant_id: Some("11111111-2222-3333-4444-555555555555".to_string()),
+            azure_default_role_arn: Some("arn:aws:iam::123456789012:role/Admin".to_string()),
+            region: Some("us-east-1".to_string()),
+            ..AwsConfig::default()
+        };
+        let dev = AwsConfig {
+            role_arn: Some("arn:aws:iam::210987654321:role/Dev".to_string()),
+            source_profile: Some("work".to_string()),
+            ..AwsConfig::default()
+        };
+
+        HashMap::from([
+            ("profile work".to_string(), work),
+            ("profile dev".to_string(), dev),
+        ])
+    }
+
+    #[test]
+    fn chained_profiles_reuse_the_source_login_under_all() {
+        let configs = chained_configs();
+        let mut credentials = HashMap::new();
+
+        let plan = LoginPlan::new(&configs, &credentials, true);
+        let logins: Vec<&str> = plan
+            .groups
+            .values()
+            .flatten()
+            .map(|(profile_name, _)| *profile_name)
+            .collect();
+        assert_eq!(logins, ["profile work"]);
+        assert_eq!(plan.chained_profiles, ["profile dev"]);
+
+        // The group stores the credential under the config section, the chain looks up the source_profile value
+        AwsCredential::upsert("profile work", &credential(), &mut credentials).unwrap();
+        let (base_profile, hops) = resolve_source_chain("profile dev", &configs).unwrap();
+        assert_eq!(base_profile, "work");
+        assert_eq!(hops.len(), 1);
+        assert!(cached_credential(&base_profile, &credentials).is_some());
+        assert!(credentials.contains_key("work"));
+
+        // Without --force the next run has nothing left to log into for the source
+        let plan = LoginPlan::new(&configs, &credentials, false);
+        assert!(plan.groups.is_empty());
+    }
+
+    fn sts_error(code: &str, message: &str) -> ErrorMetadata {
+        ErrorMetadata::builder().code(code).message(message).build()
+    }
+
+    #[test]
+    fn shorter_duration_uses_the_maximum_from_the_error() {
+        let error = sts_error(
+            "ValidationError",
+            "1 validation error detected: Value '43200' at 'durationSeconds' failed to satisfy constraint: Member must have value less than or equal to 14400",
+        );
+
+        assert_eq!(shorter_duration(&error, 43200), Some(14400));
+    }
+
+    #[test]
+    fn shorter_duration_steps_down_an_hour_at_a_time() {
+        let error = sts_error(
+            "ValidationError",
+            "The requested DurationSeconds exceeds the MaxSessionDuration set for this role.",
+        );
+
+        assert_eq!(shorter_duration(&error, 28800), Some(25200));
+        assert_eq!(shorter_duration(&error, 5000), Some(3600));
+        assert_eq!(shorter_duration(&error, 7200), Some(3600));
+
+        // A maximum that isn't below the rejected duration can't be right, so it steps down instead
+        let error = sts_error(
+            "ValidationError",
+            "DurationSeconds must be less than or equal to 43200",
+        );
+        assert_eq!(shorter_duration(&error, 28800), Some(25200));
+    }
+
+    #[test]
+    fn shorter_duration_stops_at_an_hour() {
+        let error = sts_error(
+            "ValidationError",
+            "The requested DurationSeconds exceeds the MaxSessionDuration set for this role.",
+        );
+
+        assert_eq!(shorter_duration(&error, 3600), None);
+        assert_eq!(shorter_duration(&error, 900), None);
+    }
+
+    #[test]
+    fn shorter_duration_ignores_other_errors() {
+        let throttled = sts_error("Throttling", "Rate exceeded for DurationSeconds");
+        let invalid = sts_error("ValidationError", "RoleSessionName is invalid");
+
+        assert_eq!(shorter_duration(&throttled, 28800), None);
+        assert_eq!(shorter_duration(&invalid, 28800), None);
+        assert_eq!(shorter_duration(&ErrorMetadata::default(), 28800), None);
+    }
+
+    #[test]
+    fn caps_to_the_session_duration() {
+        assert_eq!(cap_duration(43200, Some(3600)), 3600);
+        assert_eq!(cap_duration(28800, Some(14400)), 14400);
+    }
+
+    #[test]
+    fn caps_to_the_remembered_duration() {
+        let role = Role {
+            role_arn: "arn:aws:iam::123456789012:role/Admin".to_string(),
+            principal_arn: "arn:aws:iam::123456789012:saml-provider/AzureAD".to_string(),
+        };
+
+        assert_eq!(cap_to_max_duration(&role, 28800, Some(7200)), 7200);
+        assert_eq!(cap_to_max_duration(&role, 7200, Some(7200)), 7200);
+        assert_eq!(cap_to_max_duration(&role, 3600, Some(7200)), 3600);
+        assert_eq!(cap_to_max_duration(&role, 28800, None), 28800);
+    }
+
+    #[test]
+    fn durations_under_the_cap_are_kept() {
+        assert_eq!(cap_duration(3600, Some(43200)), 3600);
+        assert_eq!(cap_duration(43200, Some(43200)), 43200);
+        assert_eq!(cap_duration(28800, None), 28800);
+    }
+}
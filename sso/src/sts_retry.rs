@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Error};
+use aws_sdk_sts::error::{ProvideErrorMetadata, SdkError};
+use chrono::{DateTime, Utc};
+use file_manager::clock_skew::ClockSkew;
+use log::warn;
+use rand::Rng;
+use std::fmt::Display;
+use std::time::Duration;
+
+const MAX_ATTEMPTS: u32 = 8;
+const BASE_DELAY_MILLIS: u64 = 500;
+const MAX_DELAY_MILLIS: u64 = 20_000;
+
+/// Why an STS call failed, the first four are worth retrying
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StsErrorCategory {
+    Throttling,
+    ServerError,
+    Network,
+    ClockSkew,
+    InvalidAssertion,
+    AccessDenied,
+    Duration,
+    InvalidRequest,
+}
+
+impl StsErrorCategory {
+    pub fn classify<E: ProvideErrorMetadata>(error: &SdkError<E>) -> Self {
+        match error {
+            SdkError::TimeoutError(_)
+            | SdkError::DispatchFailure(_)
+            | SdkError::ResponseError(_) => {
+                return StsErrorCategory::Network;
+            }
+            SdkError::ConstructionFailure(_) => return StsErrorCategory::InvalidRequest,
+            _ => {}
+        }
+
+        match error.code() {
+            Some(
+                "Throttling"
+                | "ThrottlingException"
+                | "RequestLimitExceeded"
+                | "TooManyRequestsException"
+                | "PriorRequestNotComplete",
+            ) => return StsErrorCategory::Throttling,
+            // STS couldn't reach the IdP's metadata, which clears up on its own
+            Some("IDPCommunicationError" | "RequestTimeout") => return StsErrorCategory::Network,
+            // The response's Date header has corrected the signing clock by the time this is retried
+            Some("RequestExpired") => return StsErrorCategory::ClockSkew,
+            Some("SignatureDoesNotMatch")
+                if error
+                    .message()
+                    .is_some_and(|message| message.to_lowercase().contains("expired")) =>
+            {
+                return StsErrorCategory::ClockSkew
+            }
+            Some("InvalidIdentityToken" | "IDPRejectedClaim" | "ExpiredToken") => {
+                return StsErrorCategory::InvalidAssertion
+            }
+            Some("AccessDenied" | "AccessDeniedException") => {
+                return StsErrorCategory::AccessDenied
+            }
+            Some("ValidationError")
+                if error
+                    .message()
+                    .is_some_and(|message| message.to_lowercase().contains("durationseconds")) =>
+            {
+                return StsErrorCategory::Duration
+            }
+            _ => {}
+        }
+
+        let status = error.raw_response().map(|response| response.status());
+        match status {
+            Some(status) if status.as_u16() == 429 => StsErrorCategory::Throttling,
+            Some(status) if status.is_server_error() => StsErrorCategory::ServerError,
+            _ => StsErrorCategory::InvalidRequest,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            StsErrorCategory::Throttling
+                | StsErrorCategory::ServerError
+                | StsErrorCategory::Network
+                | StsErrorCategory::ClockSkew
+        )
+    }
+}
+
+impl Display for StsErrorCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StsErrorCategory::Throttling => write!(f, "throttling"),
+            StsErrorCategory::ServerError => write!(f, "STS server error"),
+            StsErrorCategory::Network => write!(f, "network error"),
+            StsErrorCategory::ClockSkew => write!(f, "clock skew"),
+            StsErrorCategory::InvalidAssertion => write!(f, "invalid SAML assertion"),
+            StsErrorCategory::AccessDenied => write!(f, "access denied"),
+            StsErrorCategory::Duration => write!(f, "session duration"),
+            StsErrorCategory::InvalidRequest => write!(f, "invalid request"),
+        }
+    }
+}
+
+/// Jittered exponential backoff for an STS call, bounded by how long its credentials stay usable
+#[derive(Clone, Debug)]
+pub struct StsRetry {
+    action: String,
+    deadline: Option<DateTime<Utc>>,
+    expiring: &'static str,
+    attempts: u32,
+}
+
+impl StsRetry {
+    /// `expiring` names what stops being usable at the deadline, e.g. the SAML assertion
+    pub fn new(
+        action: impl Into<String>,
+        deadline: Option<DateTime<Utc>>,
+        expiring: &'static str,
+    ) -> Self {
+        Self {
+            action: action.into(),
+            deadline,
+            expiring,
+            attempts: 0,
+        }
+    }
+
+    /// Waits before the next attempt, or returns the error explaining what stopped the login
+    pub async fn backoff<E>(&mut self, error: SdkError<E>) -> Result<(), Error>
+    where
+        E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    {
+        self.attempts += 1;
+        let category = StsErrorCategory::classify(&error);
+        let detail = describe(&error);
+
+        if !category.is_retryable() {
+            return Err(anyhow!(
+                "Unable to {}, STS returned a terminal error ({}): {}",
+                self.action,
+                category,
+                detail
+            ));
+        }
+
+        if self.attempts >= MAX_ATTEMPTS {
+            return Err(anyhow!(
+                "Unable to {} after {} attempts, the last error was retryable ({}): {}",
+                self.action,
+                self.attempts,
+                category,
+                detail
+            ));
+        }
+
+        let ceiling = (BASE_DELAY_MILLIS << (self.attempts - 1)).min(MAX_DELAY_MILLIS);
+        let delay = Duration::from_millis(rand::thread_rng().gen_range(ceiling / 2..=ceiling));
+
+        let retry_at = ClockSkew::now() + chrono::Duration::milliseconds(delay.as_millis() as i64);
+        if self.deadline.is_some_and(|deadline| retry_at >= deadline) {
+            return Err(anyhow!(
+                "Unable to {}, the {} expires before it can be retried after a retryable error ({}): {}",
+                self.action,
+                self.expiring,
+                category,
+                detail
+            ));
+        }
+
+        warn!(
+            "Retrying {} in {}ms after a retryable error ({}): {}",
+            self.action,
+            delay.as_millis(),
+            category,
+            detail
+        );
+        tokio::time::sleep(delay).await;
+
+        Ok(())
+    }
+}
+
+/// The service's code and message, or the innermost cause of a network failure
+fn describe<E>(error: &SdkError<E>) -> String
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+{
+    if let Some(code) = error.code() {
+        return match error.message() {
+            Some(message) => format!("{}: {}", code, message),
+            None => code.to_string(),
+        };
+    }
+
+    let mut innermost = None;
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        innermost = Some(cause);
+        source = cause.source();
+    }
+
+    match innermost {
+        Some(cause) => format!("{}: {}", error, cause),
+        None => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_sts::operation::assume_role_with_saml::AssumeRoleWithSAMLError;
+    use aws_smithy_http::body::SdkBody;
+    use aws_smithy_http::result::ConnectorError;
+    use aws_smithy_types::error::ErrorMetadata;
+
+    type StsError = SdkError<AssumeRoleWithSAMLError>;
+
+    fn service_error(status: u16, code: Option<&str>, message: &str) -> StsError {
+        let mut metadata = ErrorMetadata::builder().message(message);
+        if let Some(code) = code {
+            metadata = metadata.code(code);
+        }
+        let raw = http::Response::builder()
+            .status(status)
+            .body(SdkBody::empty())
+            .unwrap();
+
+        SdkError::service_error(AssumeRoleWithSAMLError::generic(metadata.build()), raw)
+    }
+
+    #[test]
+    fn classifies_service_errors() {
+        let cases = [
+            (
+                400,
+                Some("Throttling"),
+                "Rate exceeded",
+                StsErrorCategory::Throttling,
+            ),
+            (
+                400,
+                Some("ThrottlingException"),
+                "",
+                StsErrorCategory::Throttling,
+            ),
+            (
+                400,
+                Some("RequestLimitExceeded"),
+                "",
+                StsErrorCategory::Throttling,
+            ),
+            (429, None, "", StsErrorCategory::Throttling),
+            (500, None, "", StsErrorCategory::ServerError),
+            (
+                503,
+                Some("ServiceUnavailable"),
+                "",
+                StsErrorCategory::ServerError,
+            ),
+            (
+                400,
+                Some("IDPCommunicationError"),
+                "",
+                StsErrorCategory::Network,
+            ),
+            (400, Some("RequestExpired"), "", StsErrorCategory::ClockSkew),
+            (
+                403,
+                Some("SignatureDoesNotMatch"),
+                "Signature expired: 20261018T100000Z is now earlier than 20261018T100500Z",
+                StsErrorCategory::ClockSkew,
+            ),
+            (
+                403,
+                Some("SignatureDoesNotMatch"),
+                "The request signature we calculated does not match",
+                StsErrorCategory::InvalidRequest,
+            ),
+            (
+                400,
+                Some("InvalidIdentityToken"),
+                "",
+                StsErrorCategory::InvalidAssertion,
+            ),
+            (
+                400,
+                Some("IDPRejectedClaim"),
+                "",
+                StsErrorCategory::InvalidAssertion,
+            ),
+            (
+                400,
+                Some("ExpiredToken"),
+                "",
+                StsErrorCategory::InvalidAssertion,
+            ),
+            (
+                403,
+                Some("AccessDenied"),
+                "",
+                StsErrorCategory::AccessDenied,
+            ),
+            (
+                400,
+                Some("ValidationError"),
+                "The requested DurationSeconds exceeds the MaxSessionDuration set for this role.",
+                StsErrorCategory::Duration,
+            ),
+            (
+                400,
+                Some("ValidationError"),
+                "RoleSessionName is invalid",
+                StsErrorCategory::InvalidRequest,
+            ),
+            (400, None, "", StsErrorCategory::InvalidRequest),
+        ];
+
+        for (status, code, message, expected) in cases {
+            let category = StsErrorCategory::classify(&service_error(status, code, message));
+            assert_eq!(category, expected, "{} {:?} {}", status, code, message);
+        }
+    }
+
+    #[test]
+    fn classifies_transport_errors() {
+        let timeout: StsError = SdkError::timeout_error("timed out");
+        let dispatch: StsError =
+            SdkError::dispatch_failure(ConnectorError::io("connection refused".into()));
+        let construction: StsError = SdkError::construction_failure("missing role ARN");
+
+        assert_eq!(
+            StsErrorCategory::classify(&timeout),
+            StsErrorCategory::Network
+        );
+        assert_eq!(
+            StsErrorCategory::classify(&dispatch),
+            StsErrorCategory::Network
+        );
+        assert_eq!(
+            StsErrorCategory::classify(&construction),
+            StsErrorCategory::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        let retryable = [
+            StsErrorCategory::Throttling,
+            StsErrorCategory::ServerError,
+            StsErrorCategory::Network,
+            StsErrorCategory::ClockSkew,
+        ];
+        let terminal = [
+            StsErrorCategory::InvalidAssertion,
+            StsErrorCategory::AccessDenied,
+            StsErrorCategory::Duration,
+            StsErrorCategory::InvalidRequest,
+        ];
+
+        assert!(retryable.iter().all(StsErrorCategory::is_retryable));
+        assert!(!terminal.iter().any(StsErrorCategory::is_retryable));
+    }
+
+    #[test]
+    fn terminal_errors_are_not_retried() {
+        let mut retry = StsRetry::new("assume the role", None, "SAML assertion");
+        let error = futures::executor::block_on(retry.backoff(service_error(
+            403,
+            Some("AccessDenied"),
+            "Not authorized",
+        )))
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Unable to assume the role, STS returned a terminal error (access denied): AccessDenied: Not authorized"
+        );
+    }
+
+    #[test]
+    fn backoff_stops_at_the_deadline() {
+        // The first retry waits at least 250ms, past a deadline 100ms away
+        let deadline = ClockSkew::now() + chrono::Duration::milliseconds(100);
+        let mut retry = StsRetry::new("assume the role", Some(deadline), "SAML assertion");
+        let error = futures::executor::block_on(retry.backoff(service_error(
+            400,
+            Some("Throttling"),
+            "Rate exceeded",
+        )))
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Unable to assume the role, the SAML assertion expires before it can be retried after a retryable error (throttling): Throttling: Rate exceeded"
+        );
+    }
+}